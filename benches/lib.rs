@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use mca_cuboids::{export_cuboids, BlockCoordinates, ExportParams};
+use mca_cuboids::{export_cuboids, merge_cuboids, BlockCoordinates, ExportParams};
 use pprof::criterion::{Output, PProfProfiler};
 
 fn bench_export_cuboids(c: &mut Criterion) {
@@ -82,9 +82,37 @@ fn bench_export_cuboids(c: &mut Criterion) {
     group.finish()
 }
 
+fn bench_merge_cuboids(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    group.sample_size(10);
+
+    // hilly terrain of 256x256 columns with caves, about 4 million blocks
+    let mut blocks = vec![];
+    for x in 0..256 {
+        for z in 0..256 {
+            let height = 40 + (x * 7 + z * 3) % 24 + (x ^ z) % 5;
+            for y in 0..height {
+                if (x * 31 + y * 17 + z * 13) % 11 != 0 {
+                    blocks.push(BlockCoordinates::new(x, y as i16, z));
+                }
+            }
+        }
+    }
+
+    group.bench_function("dense terrain", |b| {
+        b.iter_batched(
+            || blocks.clone(),
+            merge_cuboids,
+            criterion::BatchSize::LargeInput,
+        )
+    });
+
+    group.finish()
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default().with_profiler(PProfProfiler::new(100, Output::Flamegraph(None)));
-    targets = bench_export_cuboids
+    targets = bench_export_cuboids, bench_merge_cuboids
 }
 criterion_main!(benches);
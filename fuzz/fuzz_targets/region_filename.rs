@@ -0,0 +1,7 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|filename: &str| {
+    let _ = mca_cuboids::fuzzing::region_filename(filename);
+});
@@ -0,0 +1,220 @@
+use crate::{chunk_compound_shapes, cuboid_shapes, export_cuboids, ColliderOptions, ExportParams};
+use anyhow::Result;
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::prelude::*;
+use bevy::math::Vec3;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::transform::components::Transform;
+use bevy_rapier3d::prelude::{Collider, RigidBody};
+
+/// Spawns the colliders requested by [`LevelColliders`] components.
+pub struct LevelCollidersPlugin;
+
+impl Plugin for LevelCollidersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (start_exports, finish_exports).chain());
+    }
+}
+
+/// Exports the level once the component is added and spawns every cuboid as a
+/// child entity with a `Transform` and a `Collider`. The entity itself becomes
+/// a fixed `RigidBody` and gets [`LevelCollidersLoaded`] or
+/// [`LevelCollidersFailed`] once the export is done.
+#[derive(Component, Clone, Default)]
+#[require(Transform)]
+pub struct LevelColliders {
+    pub lvl_path: String,
+    pub params: ExportParams,
+    pub options: ColliderOptions,
+    /// One compound collider per chunk instead of one collider per cuboid.
+    pub per_chunk: bool,
+    /// Exports within the frame instead of on the `AsyncComputeTaskPool`.
+    pub blocking: bool,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelCollidersLoaded;
+
+/// Error of the export, with its causes.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct LevelCollidersFailed(pub String);
+
+#[derive(Component)]
+struct ExportTask(Task<Result<Vec<(Transform, Collider)>>>);
+
+fn start_exports(
+    mut commands: Commands,
+    requests: Query<(Entity, &LevelColliders), Added<LevelColliders>>,
+) {
+    for (entity, request) in &requests {
+        if request.blocking {
+            spawn_colliders(&mut commands, entity, build_colliders(request));
+        } else {
+            let request = request.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move { build_colliders(&request) });
+            commands.entity(entity).insert(ExportTask(task));
+        }
+    }
+}
+
+fn finish_exports(mut commands: Commands, mut tasks: Query<(Entity, &mut ExportTask)>) {
+    for (entity, mut task) in &mut tasks {
+        if let Some(result) = check_ready(&mut task.0) {
+            commands.entity(entity).remove::<ExportTask>();
+            spawn_colliders(&mut commands, entity, result);
+        }
+    }
+}
+
+fn build_colliders(request: &LevelColliders) -> Result<Vec<(Transform, Collider)>> {
+    let cuboids = export_cuboids(&request.lvl_path, request.params.clone())?;
+    let shapes = if request.per_chunk {
+        chunk_compound_shapes(&cuboids, &request.options)
+            .into_iter()
+            .map(|(_, position, shape)| (position, shape))
+            .collect()
+    } else {
+        cuboid_shapes(&cuboids, &request.options)
+    };
+
+    Ok(shapes
+        .into_iter()
+        .map(|(position, shape)| {
+            let translation = position.translation.vector;
+            (
+                Transform::from_translation(Vec3::new(translation.x, translation.y, translation.z)),
+                Collider::from(shape),
+            )
+        })
+        .collect())
+}
+
+fn spawn_colliders(
+    commands: &mut Commands,
+    entity: Entity,
+    result: Result<Vec<(Transform, Collider)>>,
+) {
+    let mut level = commands.entity(entity);
+    match result {
+        Ok(colliders) => {
+            level
+                .insert((RigidBody::Fixed, LevelCollidersLoaded))
+                .with_children(|parent| {
+                    for collider in colliders {
+                        parent.spawn(collider);
+                    }
+                });
+        }
+        Err(error) => {
+            level.insert(LevelCollidersFailed(format!("{error:#}")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{test_dir, write_world};
+    use crate::BlockCoordinates;
+    use bevy::ecs::hierarchy::Children;
+    use bevy::tasks::TaskPool;
+
+    fn load(request: LevelColliders) -> (App, Entity) {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+        let mut app = App::new();
+        app.add_plugins(LevelCollidersPlugin);
+        let entity = app.world_mut().spawn(request).id();
+        for _ in 0..100 {
+            app.update();
+            let level = app.world().entity(entity);
+            if level.contains::<LevelCollidersLoaded>() || level.contains::<LevelCollidersFailed>()
+            {
+                break;
+            }
+        }
+
+        (app, entity)
+    }
+
+    #[test]
+    fn spawn_level_colliders() {
+        let dir = test_dir("spawn_level_colliders");
+        let b = BlockCoordinates::new;
+        let blocks: Vec<(BlockCoordinates, &str)> = (0..20)
+            .flat_map(|x| {
+                [
+                    (b(x, 0, 0), "minecraft:stone"),
+                    (b(x, 2, 0), "minecraft:stone"),
+                ]
+            })
+            .collect();
+        write_world(&dir, &blocks).unwrap();
+
+        for (per_chunk, blocking, colliders) in
+            [(false, true, 2), (false, false, 2), (true, false, 2)]
+        {
+            let (app, entity) = load(LevelColliders {
+                lvl_path: dir.clone(),
+                params: ExportParams {
+                    start: b(0, 0, 0),
+                    end: b(40, 10, 10),
+                    ..Default::default()
+                },
+                per_chunk,
+                blocking,
+                ..Default::default()
+            });
+            let level = app.world().entity(entity);
+
+            assert!(level.contains::<LevelCollidersLoaded>());
+            assert_eq!(level.get::<RigidBody>(), Some(&RigidBody::Fixed));
+            let children = level.get::<Children>().unwrap();
+            assert_eq!(children.len(), colliders);
+            for child in children {
+                assert!(app.world().entity(*child).contains::<Collider>());
+            }
+        }
+
+        let (app, entity) = load(LevelColliders {
+            lvl_path: dir,
+            params: ExportParams {
+                start: b(0, 0, 0),
+                end: b(40, 10, 10),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let transforms: Vec<Vec3> = app
+            .world()
+            .entity(entity)
+            .get::<Children>()
+            .unwrap()
+            .iter()
+            .map(|child| {
+                app.world()
+                    .entity(child)
+                    .get::<Transform>()
+                    .unwrap()
+                    .translation
+            })
+            .collect();
+        assert_eq!(
+            transforms,
+            vec![Vec3::new(10.0, 0.5, 0.5), Vec3::new(10.0, 2.5, 0.5)]
+        );
+    }
+    #[test]
+    fn report_failed_exports() {
+        let (app, entity) = load(LevelColliders {
+            lvl_path: "./does/not/exist".to_owned(),
+            blocking: true,
+            ..Default::default()
+        });
+
+        assert!(app
+            .world()
+            .entity(entity)
+            .contains::<LevelCollidersFailed>());
+    }
+}
@@ -0,0 +1,346 @@
+#![deny(clippy::all, clippy::pedantic, clippy::cognitive_complexity)]
+#![allow(clippy::cast_possible_wrap)]
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use mca_cuboids::{
+    encode_cuboids, export_document, BlockCoordinates, BlockSequence, CuboidExport, EncodeOptions,
+    ExportParams, WorldHeight,
+};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Default of the `commandModificationBlockLimit` game rule.
+const FILL_BLOCK_LIMIT: u64 = 32768;
+
+/// Exports the blocks of a Minecraft world as merged cuboids.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// World directory, the one containing `level.dat`
+    world: PathBuf,
+    /// First corner of the exported area as `x,y,z`
+    #[arg(long, value_parser = parse_coordinates, allow_hyphen_values = true)]
+    from: BlockCoordinates,
+    /// Second corner of the exported area as `x,y,z`
+    #[arg(long, value_parser = parse_coordinates, allow_hyphen_values = true)]
+    to: BlockCoordinates,
+    #[arg(long, value_enum, default_value_t = Dimension::Overworld)]
+    dimension: Dimension,
+    /// Block to skip, e.g. `minecraft:stone`, can be repeated
+    #[arg(long)]
+    skip: Vec<String>,
+    /// Block to export exclusively, can be repeated
+    #[arg(long)]
+    only: Vec<String>,
+    /// Biome to export exclusively, e.g. `minecraft:plains`, can be repeated
+    #[arg(long)]
+    biome: Vec<String>,
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+    /// Block placed by the `/fill` commands
+    #[arg(long, default_value = "minecraft:stone")]
+    fill_block: String,
+    /// File to write the cuboids to instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Directory to cache parsed chunks in between runs
+    #[arg(long)]
+    cache_dir: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Dimension {
+    Overworld,
+    Nether,
+    End,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Versioned `CuboidExport` document
+    Json,
+    Csv,
+    /// `/fill` commands, split to respect the game's block limit
+    Fill,
+    Obj,
+    /// Compressed binary encoding, see `mca_cuboids::CuboidReader`
+    Binary,
+}
+
+impl Dimension {
+    fn region_dir(self) -> &'static str {
+        match self {
+            Dimension::Overworld => "region",
+            Dimension::Nether => "DIM-1/region",
+            Dimension::End => "DIM1/region",
+        }
+    }
+
+    fn height(self) -> WorldHeight {
+        match self {
+            Dimension::Overworld => WorldHeight::OVERWORLD,
+            Dimension::Nether => WorldHeight::NETHER,
+            Dimension::End => WorldHeight::END,
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let region_dir = args.world.join(args.dimension.region_dir());
+    let lvl_path = region_dir
+        .to_str()
+        .context("World path is not valid unicode")?;
+
+    let started = Instant::now();
+    let document = export_document(
+        lvl_path,
+        ExportParams {
+            start: args.from,
+            end: args.to,
+            skip_blocks: args.skip,
+            only_blocks: args.only,
+            only_biomes: args.biome,
+            cache_dir: args.cache_dir,
+            world_height: args.dimension.height(),
+            ..Default::default()
+        },
+    )?;
+    let elapsed = started.elapsed();
+
+    let blocks: u64 = document.cuboids.iter().map(BlockSequence::volume).sum();
+    eprintln!(
+        "Read {blocks} blocks into {} cuboids in {elapsed:.2?}",
+        document.cuboids.len()
+    );
+
+    match &args.output {
+        Some(path) => {
+            let file = File::create(path).context(format!("Cannot create {}", path.display()))?;
+            write_cuboids(
+                &mut BufWriter::new(file),
+                &document,
+                args.format,
+                &args.fill_block,
+            )
+        }
+        None => write_cuboids(
+            &mut io::stdout().lock(),
+            &document,
+            args.format,
+            &args.fill_block,
+        ),
+    }
+}
+
+fn parse_coordinates(value: &str) -> Result<BlockCoordinates> {
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let [x, y, z] = parts.as_slice() else {
+        bail!("Expected x,y,z but got {value}");
+    };
+
+    Ok(BlockCoordinates::new(x.parse()?, y.parse()?, z.parse()?))
+}
+
+fn write_cuboids(
+    out: &mut impl Write,
+    document: &CuboidExport,
+    format: Format,
+    fill_block: &str,
+) -> Result<()> {
+    let cuboids = &document.cuboids;
+    match format {
+        Format::Json => {
+            serde_json::to_writer(&mut *out, document)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            writeln!(out, "start_x,start_y,start_z,end_x,end_y,end_z")?;
+            for c in cuboids {
+                let (s, e) = (&c.start, &c.end);
+                writeln!(out, "{},{},{},{},{},{}", s.x, s.y, s.z, e.x, e.y, e.z)?;
+            }
+        }
+        Format::Fill => {
+            for c in cuboids.iter().flat_map(split_for_fill) {
+                let (s, e) = (&c.start, &c.end);
+                writeln!(
+                    out,
+                    "fill {} {} {} {} {} {} {fill_block}",
+                    s.x, s.y, s.z, e.x, e.y, e.z
+                )?;
+            }
+        }
+        Format::Obj => write_obj(out, cuboids)?,
+        Format::Binary => encode_cuboids(
+            &mut *out,
+            cuboids,
+            &EncodeOptions {
+                origin: Some(document.metadata.start.clone()),
+                compress: true,
+            },
+        )?,
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// Halves the longest axis until every part can be placed by a single `/fill`.
+fn split_for_fill(cuboid: &BlockSequence) -> Vec<BlockSequence> {
+    if cuboid.volume() <= FILL_BLOCK_LIMIT {
+        return vec![cuboid.clone()];
+    }
+
+    let size = cuboid.size();
+    let (mut first, mut second) = (cuboid.clone(), cuboid.clone());
+    if size.x >= i32::from(size.y) && size.x >= size.z {
+        first.end.x = cuboid.start.x + size.x / 2 - 1;
+        second.start.x = first.end.x + 1;
+    } else if i32::from(size.y) >= size.z {
+        first.end.y = cuboid.start.y + size.y / 2 - 1;
+        second.start.y = first.end.y + 1;
+    } else {
+        first.end.z = cuboid.start.z + size.z / 2 - 1;
+        second.start.z = first.end.z + 1;
+    }
+
+    let mut parts = split_for_fill(&first);
+    parts.extend(split_for_fill(&second));
+    parts
+}
+
+/// One closed box of 8 vertices and 6 outward facing quads per cuboid, every
+/// block spans one unit.
+fn write_obj(out: &mut impl Write, cuboids: &[BlockSequence]) -> Result<()> {
+    const FACES: [[usize; 4]; 6] = [
+        [0, 3, 2, 1],
+        [4, 5, 6, 7],
+        [0, 1, 5, 4],
+        [3, 7, 6, 2],
+        [0, 4, 7, 3],
+        [1, 2, 6, 5],
+    ];
+
+    for (i, c) in cuboids.iter().enumerate() {
+        let min = [c.start.x, i32::from(c.start.y), c.start.z];
+        let max = [c.end.x + 1, i32::from(c.end.y) + 1, c.end.z + 1];
+
+        writeln!(out, "o cuboid_{i}")?;
+        for vertex in 0..8 {
+            let [x, y, z] = [0, 1, 2].map(|axis| {
+                let corner = match (axis, vertex) {
+                    (0, 1 | 2 | 5 | 6) | (1, 2 | 3 | 6 | 7) | (2, 4..=7) => max,
+                    _ => min,
+                };
+                corner[axis]
+            });
+            writeln!(out, "v {x} {y} {z}")?;
+        }
+        for face in FACES {
+            let [a, b, c, d] = face.map(|vertex| i * 8 + vertex + 1);
+            writeln!(out, "f {a} {b} {c} {d}")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mca_cuboids::ExportMetadata;
+
+    fn written(cuboids: &[BlockSequence], format: Format) -> String {
+        let document = CuboidExport::new(
+            ExportMetadata::new(None, &ExportParams::default()),
+            cuboids.to_vec(),
+        );
+        let mut out = vec![];
+        write_cuboids(&mut out, &document, format, "minecraft:glass").unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parse_corners() {
+        assert_eq!(
+            parse_coordinates("-10, -64,5").unwrap(),
+            BlockCoordinates::new(-10, -64, 5)
+        );
+        assert!(parse_coordinates("1,2").is_err());
+        assert!(parse_coordinates("1,2,3,4").is_err());
+        assert!(parse_coordinates("1,a,3").is_err());
+    }
+    #[test]
+    fn write_csv_and_fill() {
+        let cuboids = vec![BlockSequence::new(
+            BlockCoordinates::new(-1, 2, 3),
+            BlockCoordinates::new(4, 5, 6),
+        )];
+
+        assert_eq!(
+            written(&cuboids, Format::Csv),
+            "start_x,start_y,start_z,end_x,end_y,end_z\n-1,2,3,4,5,6\n"
+        );
+        assert_eq!(
+            written(&cuboids, Format::Fill),
+            "fill -1 2 3 4 5 6 minecraft:glass\n"
+        );
+    }
+    #[test]
+    fn write_binary() {
+        let cuboids = vec![BlockSequence::new(
+            BlockCoordinates::new(-1, 2, 3),
+            BlockCoordinates::new(4, 5, 6),
+        )];
+        let mut out = vec![];
+        let document = CuboidExport::new(
+            ExportMetadata::new(None, &ExportParams::default()),
+            cuboids.clone(),
+        );
+        write_cuboids(&mut out, &document, Format::Binary, "").unwrap();
+
+        assert_eq!(
+            mca_cuboids::decode_cuboids(out.as_slice()).unwrap(),
+            cuboids
+        );
+    }
+    #[test]
+    fn split_large_fills() {
+        let cuboid = BlockSequence::new(
+            BlockCoordinates::new(0, -64, 0),
+            BlockCoordinates::new(99, 0, 99),
+        );
+        let parts = split_for_fill(&cuboid);
+
+        assert!(parts.iter().all(|part| part.volume() <= FILL_BLOCK_LIMIT));
+        assert_eq!(
+            parts.iter().map(BlockSequence::volume).sum::<u64>(),
+            cuboid.volume()
+        );
+        assert_eq!(
+            mca_cuboids::verify(&parts, &cuboid.blocks().collect::<Vec<_>>()),
+            Ok(())
+        );
+    }
+    #[test]
+    fn write_obj_box() {
+        let obj = written(
+            &[BlockSequence::new(
+                BlockCoordinates::new(0, 0, 0),
+                BlockCoordinates::new(1, 0, 0),
+            )],
+            Format::Obj,
+        );
+        let lines: Vec<&str> = obj.lines().collect();
+
+        assert_eq!(lines[0], "o cuboid_0");
+        assert_eq!(lines[1], "v 0 0 0");
+        assert_eq!(lines[7], "v 2 1 1");
+        assert_eq!(lines.iter().filter(|l| l.starts_with("f ")).count(), 6);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::chunk::BIOME_CELL_SIZE;
+use crate::read::{load_cached_chunk, read_chunk, read_level_with, RegionFile, TypedBlocks};
+use crate::{BlockCoordinates, BlockSequence, ExportParams};
+use anyhow::Result;
+use std::fs::DirEntry;
+
+#[allow(clippy::cast_possible_truncation)]
+const CELL_SIZE: i32 = BIOME_CELL_SIZE as i32;
+
+/// Merges the biome cells overlapping the area of `params` into cuboids per
+/// biome, clipped to the area. `params.only_biomes` limits the biomes,
+/// block filters and `params.shape` are ignored.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn read_biome_cuboids(
+    lvl_path: &str,
+    params: ExportParams,
+) -> Result<Vec<(BlockSequence, String)>> {
+    // the shape would otherwise still skip region files and chunks
+    let params = ExportParams {
+        shape: None,
+        ..params
+    }
+    .normalized()?;
+    let area = BlockSequence::new(params.start.clone(), params.end.clone());
+    let mut cells = TypedBlocks::default();
+    read_level_with(lvl_path, params, read_biome_file, |file_cells| {
+        cells.extend(file_cells);
+    })?;
+
+    Ok(cells
+        .merge()
+        .into_iter()
+        .filter_map(|(cuboid, biome)| {
+            let blocks = BlockSequence::new(
+                BlockCoordinates::new(
+                    cuboid.start.x * CELL_SIZE,
+                    cuboid.start.y * CELL_SIZE as i16,
+                    cuboid.start.z * CELL_SIZE,
+                ),
+                BlockCoordinates::new(
+                    cuboid.end.x * CELL_SIZE + CELL_SIZE - 1,
+                    cuboid.end.y * CELL_SIZE as i16 + CELL_SIZE as i16 - 1,
+                    cuboid.end.z * CELL_SIZE + CELL_SIZE - 1,
+                ),
+            );
+
+            Some((blocks.intersection(&area)?, biome))
+        })
+        .collect())
+}
+
+/// Biome cells of a region file overlapping the export area, with the
+/// coordinates of a cell in the place of block coordinates.
+#[allow(clippy::cast_possible_truncation)]
+fn read_biome_file(dir_entry: &DirEntry, params: &ExportParams) -> Result<TypedBlocks> {
+    let mut file = RegionFile::open(dir_entry, params)?;
+    let cell_range = |start: i32, end: i32| start.div_euclid(CELL_SIZE)..=end.div_euclid(CELL_SIZE);
+    let x_cells = cell_range(*file.x_range.start(), *file.x_range.end());
+    let z_cells = cell_range(*file.z_range.start(), *file.z_range.end());
+    let y_cells = cell_range(i32::from(params.start.y), i32::from(params.end.y));
+    let mut cells = TypedBlocks::default();
+
+    file.for_each_chunk(
+        params,
+        |region, cache, (chunk_x, chunk_z), (min_x, min_z)| {
+            let mut push_cell = |x: usize, y: i16, z: usize, biome: &str| {
+                let cell = BlockCoordinates::new(
+                    min_x / CELL_SIZE + x as i32,
+                    y,
+                    min_z / CELL_SIZE + z as i32,
+                );
+
+                if x_cells.contains(&cell.x)
+                    && z_cells.contains(&cell.z)
+                    && y_cells.contains(&i32::from(y))
+                    && (params.only_biomes.is_empty()
+                        || params.only_biomes.iter().any(|only| only == biome))
+                {
+                    cells.push(cell, biome);
+                }
+            };
+
+            if let Some(cache) = cache {
+                if let Some(cached) = load_cached_chunk(region, cache, chunk_x, chunk_z)? {
+                    cached
+                        .biome_cells()
+                        .for_each(|(x, y, z, biome)| push_cell(x, y, z, biome));
+                }
+            } else if let Some(chunk) = read_chunk(region, chunk_x, chunk_z)? {
+                chunk
+                    .biome_cells()
+                    .for_each(|(x, y, z, biome)| push_cell(x, y, z, biome));
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{set_section_biome, test_dir, write_world};
+    use crate::ExportShape;
+
+    #[test]
+    fn read_biome_cuboids_merged_and_clipped() {
+        let dir = test_dir("read_biome_cuboids");
+        let b = BlockCoordinates::new;
+        write_world(
+            &dir,
+            &[
+                (b(0, 0, 0), "minecraft:stone"),
+                (b(16, 0, 0), "minecraft:stone"),
+            ],
+        )
+        .unwrap();
+        set_section_biome(&dir, (1, 0, 0), "minecraft:desert").unwrap();
+        let params = ExportParams {
+            start: b(2, 1, 3),
+            end: b(20, 5, 3),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            read_biome_cuboids(&dir, params.clone()).unwrap(),
+            vec![
+                (
+                    BlockSequence::new(b(16, 1, 3), b(20, 5, 3)),
+                    "minecraft:desert".to_owned()
+                ),
+                (
+                    BlockSequence::new(b(2, 1, 3), b(15, 5, 3)),
+                    "minecraft:plains".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            read_biome_cuboids(
+                &dir,
+                ExportParams {
+                    only_biomes: vec!["minecraft:plains".to_owned()],
+                    ..params
+                }
+            )
+            .unwrap(),
+            vec![(
+                BlockSequence::new(b(2, 1, 3), b(15, 5, 3)),
+                "minecraft:plains".to_owned()
+            )]
+        );
+    }
+    #[test]
+    fn read_biome_cuboids_ignore_shape() {
+        let dir = test_dir("read_biome_cuboids_shape");
+        let b = BlockCoordinates::new;
+        write_world(
+            &dir,
+            &[
+                (b(0, 0, 0), "minecraft:stone"),
+                (b(16, 0, 0), "minecraft:stone"),
+            ],
+        )
+        .unwrap();
+        set_section_biome(&dir, (1, 0, 0), "minecraft:desert").unwrap();
+        let params = ExportParams {
+            start: b(2, 1, 3),
+            end: b(20, 5, 3),
+            ..Default::default()
+        };
+        let expected = read_biome_cuboids(&dir, params.clone()).unwrap();
+
+        // neither the chunk outside of the shape nor a shape outside of the
+        // area changes the biomes
+        for shape in [
+            ExportShape::Boxes(vec![BlockSequence::new(b(0, 0, 0), b(3, 3, 3))]),
+            ExportShape::Boxes(vec![BlockSequence::new(b(500, 0, 0), b(501, 1, 1))]),
+        ] {
+            assert_eq!(
+                read_biome_cuboids(
+                    &dir,
+                    ExportParams {
+                        shape: Some(shape),
+                        ..params.clone()
+                    }
+                )
+                .unwrap(),
+                expected
+            );
+        }
+    }
+}
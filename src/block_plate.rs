@@ -10,12 +10,21 @@ pub struct BlockPlate {
 impl BlockPlate {
     pub(crate) fn add_block(&mut self, block: BlockCoordinates) {
         let z = block.z;
-        self.internal.entry(z).or_insert_with(Vec::new).push(block);
+        self.internal.entry(z).or_default().push(block);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.internal.values().map(Vec::len).sum()
+    }
+
+    #[cfg(all(feature = "debug-verify", debug_assertions))]
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = &BlockCoordinates> {
+        self.internal.values().flatten()
     }
 
     pub(crate) fn rows(self) -> Vec<(i32, Vec<BlockCoordinates>)> {
         let mut rows: Vec<(i32, Vec<BlockCoordinates>)> = self.internal.into_iter().collect();
-        rows.sort_by(|(z1, ..), (z2, ..)| z1.cmp(z2));
+        rows.sort_by_key(|(z, ..)| *z);
 
         rows
     }
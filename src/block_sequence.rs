@@ -1,14 +1,18 @@
 use crate::BlockCoordinates;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+/// Axis, coordinate along the axis and extents along the other two axes.
+pub(crate) type Face = (u8, i64, [i32; 4]);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub struct BlockSequence {
     pub start: BlockCoordinates,
     pub end: BlockCoordinates,
 }
 
 impl BlockSequence {
-    pub(crate) fn new(start: BlockCoordinates, end: BlockCoordinates) -> BlockSequence {
+    #[must_use]
+    pub fn new(start: BlockCoordinates, end: BlockCoordinates) -> BlockSequence {
         BlockSequence { start, end }
     }
 
@@ -33,4 +37,271 @@ impl BlockSequence {
     pub(crate) fn has_z_end_on(&self, z: i32) -> bool {
         self.end.z == z
     }
+
+    /// Number of blocks along every axis.
+    #[must_use]
+    pub fn size(&self) -> BlockCoordinates {
+        BlockCoordinates::new(
+            self.end.x - self.start.x + 1,
+            self.end.y - self.start.y + 1,
+            self.end.z - self.start.z + 1,
+        )
+    }
+
+    #[must_use]
+    pub fn volume(&self) -> u64 {
+        let size = self.size();
+
+        u64::from(size.x.unsigned_abs())
+            * u64::from(size.y.unsigned_abs())
+            * u64::from(size.z.unsigned_abs())
+    }
+
+    /// Centre of the cuboid in world space, where the block `(0, 0, 0)` spans
+    /// from `0.0` to `1.0` on every axis like it does in the game.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn center(&self) -> [f32; 3] {
+        let center = |start: i32, end: i32| (start as f32 + end as f32 + 1.0) / 2.0;
+
+        [
+            center(self.start.x, self.end.x),
+            center(i32::from(self.start.y), i32::from(self.end.y)),
+            center(self.start.z, self.end.z),
+        ]
+    }
+
+    /// Half of the cuboid size in world space, as expected by most physics engines.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn half_extents(&self) -> [f32; 3] {
+        let size = self.size();
+
+        [
+            size.x as f32 / 2.0,
+            f32::from(size.y) / 2.0,
+            size.z as f32 / 2.0,
+        ]
+    }
+
+    #[must_use]
+    pub fn contains(&self, block: &BlockCoordinates) -> bool {
+        (self.start.x..=self.end.x).contains(&block.x)
+            && (self.start.y..=self.end.y).contains(&block.y)
+            && (self.start.z..=self.end.z).contains(&block.z)
+    }
+
+    /// Whether both cuboids share at least one block.
+    #[must_use]
+    pub fn intersects(&self, other: &BlockSequence) -> bool {
+        self.start.x <= other.end.x
+            && other.start.x <= self.end.x
+            && self.start.y <= other.end.y
+            && other.start.y <= self.end.y
+            && self.start.z <= other.end.z
+            && other.start.z <= self.end.z
+    }
+
+    /// The smallest cuboid containing both cuboids.
+    #[must_use]
+    pub fn union(&self, other: &BlockSequence) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(
+                self.start.x.min(other.start.x),
+                self.start.y.min(other.start.y),
+                self.start.z.min(other.start.z),
+            ),
+            BlockCoordinates::new(
+                self.end.x.max(other.end.x),
+                self.end.y.max(other.end.y),
+                self.end.z.max(other.end.z),
+            ),
+        )
+    }
+
+    /// The blocks shared by both cuboids.
+    #[must_use]
+    pub fn intersection(&self, other: &BlockSequence) -> Option<BlockSequence> {
+        self.intersects(other).then(|| {
+            BlockSequence::new(
+                BlockCoordinates::new(
+                    self.start.x.max(other.start.x),
+                    self.start.y.max(other.start.y),
+                    self.start.z.max(other.start.z),
+                ),
+                BlockCoordinates::new(
+                    self.end.x.min(other.end.x),
+                    self.end.y.min(other.end.y),
+                    self.end.z.min(other.end.z),
+                ),
+            )
+        })
+    }
+
+    /// The blocks of the cuboid outside of `other` as at most six cuboids:
+    /// the slabs below and above, then the parts around `other` in between.
+    #[must_use]
+    pub fn subtract(&self, other: &BlockSequence) -> Vec<BlockSequence> {
+        let Some(hole) = self.intersection(other) else {
+            return vec![self.clone()];
+        };
+        let (start, end) = (&self.start, &self.end);
+        let (hs, he) = (&hole.start, &hole.end);
+        let mut parts = vec![];
+
+        if hs.y > start.y {
+            parts.push(BlockSequence::new(
+                start.clone(),
+                BlockCoordinates::new(end.x, hs.y - 1, end.z),
+            ));
+        }
+        if hs.z > start.z {
+            parts.push(BlockSequence::new(
+                BlockCoordinates::new(start.x, hs.y, start.z),
+                BlockCoordinates::new(end.x, he.y, hs.z - 1),
+            ));
+        }
+        if hs.x > start.x {
+            parts.push(BlockSequence::new(
+                BlockCoordinates::new(start.x, hs.y, hs.z),
+                BlockCoordinates::new(hs.x - 1, he.y, he.z),
+            ));
+        }
+        if he.x < end.x {
+            parts.push(BlockSequence::new(
+                BlockCoordinates::new(he.x + 1, hs.y, hs.z),
+                BlockCoordinates::new(end.x, he.y, he.z),
+            ));
+        }
+        if he.z < end.z {
+            parts.push(BlockSequence::new(
+                BlockCoordinates::new(start.x, hs.y, he.z + 1),
+                BlockCoordinates::new(end.x, he.y, end.z),
+            ));
+        }
+        if he.y < end.y {
+            parts.push(BlockSequence::new(
+                BlockCoordinates::new(start.x, he.y + 1, start.z),
+                end.clone(),
+            ));
+        }
+
+        parts
+    }
+
+    /// Whether `other` lies next to the sequence and covers exactly the same
+    /// face, so both together form a single cuboid.
+    pub(crate) fn shares_face(&self, other: &BlockSequence) -> bool {
+        let same_x = self.start.x == other.start.x && self.end.x == other.end.x;
+        let same_y = self.start.y == other.start.y && self.end.y == other.end.y;
+        let same_z = self.start.z == other.start.z && self.end.z == other.end.z;
+        let next_x = self.end.x + 1 == other.start.x || other.end.x + 1 == self.start.x;
+        let next_y = i32::from(self.end.y) + 1 == i32::from(other.start.y)
+            || i32::from(other.end.y) + 1 == i32::from(self.start.y);
+        let next_z = self.end.z + 1 == other.start.z || other.end.z + 1 == self.start.z;
+
+        (next_x && same_y && same_z) || (same_x && next_y && same_z) || (same_x && same_y && next_z)
+    }
+
+    /// Lower and upper face along x, y and z, the upper one lying just past the
+    /// sequence. Two sequences share a face when the upper face of one is the
+    /// lower face of the other.
+    pub(crate) fn faces(&self) -> [(Face, Face); 3] {
+        let (start, end) = (&self.start, &self.end);
+        let (start_y, end_y) = (i32::from(start.y), i32::from(end.y));
+        let x = [start_y, end_y, start.z, end.z];
+        let y = [start.x, end.x, start.z, end.z];
+        let z = [start.x, end.x, start_y, end_y];
+
+        [
+            ((0, start.x.into(), x), (0, i64::from(end.x) + 1, x)),
+            ((1, start_y.into(), y), (1, i64::from(end_y) + 1, y)),
+            ((2, start.z.into(), z), (2, i64::from(end.z) + 1, z)),
+        ]
+    }
+
+    /// Iterates over every contained block, in x, then z, then y order.
+    pub fn blocks(&self) -> impl Iterator<Item = BlockCoordinates> + '_ {
+        (self.start.y..=self.end.y).flat_map(move |y| {
+            (self.start.z..=self.end.z).flat_map(move |z| {
+                (self.start.x..=self.end.x).map(move |x| BlockCoordinates::new(x, y, z))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cuboid(start: (i32, i16, i32), end: (i32, i16, i32)) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(start.0, start.1, start.2),
+            BlockCoordinates::new(end.0, end.1, end.2),
+        )
+    }
+
+    #[test]
+    fn size_and_volume() {
+        let c = cuboid((-1, -64, 2), (2, -63, 2));
+
+        assert_eq!(c.size(), BlockCoordinates::new(4, 2, 1));
+        assert_eq!(c.volume(), 8);
+        assert_eq!(c.blocks().count(), 8);
+    }
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn center_and_half_extents() {
+        let c = cuboid((0, 0, 0), (0, 0, 0));
+        assert_eq!(c.center(), [0.5, 0.5, 0.5]);
+        assert_eq!(c.half_extents(), [0.5, 0.5, 0.5]);
+
+        let c = cuboid((-2, 10, 4), (1, 10, 5));
+        assert_eq!(c.center(), [0.0, 10.5, 5.0]);
+        assert_eq!(c.half_extents(), [2.0, 0.5, 1.0]);
+    }
+    #[test]
+    fn union_and_intersection() {
+        let a = cuboid((0, 0, 0), (3, 3, 3));
+        let b = cuboid((2, 1, -5), (6, 2, 2));
+        let c = cuboid((4, 0, 0), (4, 0, 0));
+
+        assert!(a.intersects(&b));
+        assert!(!a.intersects(&c));
+        assert_eq!(a.union(&b), cuboid((0, 0, -5), (6, 3, 3)));
+        assert_eq!(a.intersection(&b), Some(cuboid((2, 1, 0), (3, 2, 2))));
+        assert_eq!(a.intersection(&c), None);
+    }
+    #[test]
+    fn subtract() {
+        let a = cuboid((0, 0, 0), (3, 3, 3));
+
+        assert_eq!(a.subtract(&cuboid((5, 0, 0), (6, 0, 0))), vec![a.clone()]);
+        assert_eq!(a.subtract(&cuboid((-1, -1, -1), (4, 4, 4))), vec![]);
+        assert_eq!(
+            a.subtract(&cuboid((0, 2, 0), (3, 5, 3))),
+            vec![cuboid((0, 0, 0), (3, 1, 3))]
+        );
+
+        let hole = cuboid((1, 1, 1), (2, 2, 2));
+        let parts = a.subtract(&hole);
+        assert_eq!(parts.len(), 6);
+        assert_eq!(
+            parts.iter().map(BlockSequence::volume).sum::<u64>(),
+            a.volume() - hole.volume()
+        );
+        assert!(parts.iter().all(|part| !part.intersects(&hole)));
+    }
+    #[test]
+    fn contains_and_shares_face() {
+        let c = cuboid((0, 0, 0), (1, 1, 1));
+
+        assert!(c.contains(&BlockCoordinates::new(1, 0, 1)));
+        assert!(!c.contains(&BlockCoordinates::new(2, 0, 1)));
+        assert!(c.shares_face(&cuboid((2, 0, 0), (4, 1, 1))));
+        assert!(c.shares_face(&cuboid((0, -3, 0), (1, -1, 1))));
+        assert!(!c.shares_face(&cuboid((2, 0, 0), (2, 0, 0))));
+        assert!(!c.shares_face(&cuboid((3, 0, 0), (4, 1, 1))));
+        assert!(!c.shares_face(&cuboid((2, 2, 0), (3, 3, 1))));
+    }
 }
@@ -16,15 +16,21 @@ impl BlockStack {
     }
     pub(crate) fn add_block(&mut self, block: BlockCoordinates) {
         let y = block.y as isize;
+        self.internal.entry(y).or_default().add_block(block);
+    }
+
+    #[cfg(all(feature = "debug-verify", debug_assertions))]
+    pub(crate) fn blocks(&self) -> Vec<BlockCoordinates> {
         self.internal
-            .entry(y)
-            .or_insert_with(BlockPlate::default)
-            .add_block(block);
+            .values()
+            .flat_map(BlockPlate::blocks)
+            .cloned()
+            .collect()
     }
 
     pub fn plates(self) -> Vec<(isize, BlockPlate)> {
         let mut plates: Vec<(isize, BlockPlate)> = self.internal.into_iter().collect();
-        plates.sort_by(|(y1, ..), (y2, ..)| y1.cmp(y2));
+        plates.sort_by_key(|(y, ..)| *y);
 
         plates
     }
@@ -0,0 +1,162 @@
+use crate::chunk::{BlockState, Chunk};
+use anyhow::{Context, Result};
+use fastnbt::{from_bytes, to_bytes};
+use rustc_hash::{FxHashMap, FxHasher};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// On-disk cache of the non-air blocks of every chunk of a single region file.
+/// Entries are keyed by the chunk's modification time from the region header
+/// and by a hash of its compressed bytes, so a chunk is parsed again after it
+/// was saved with other content, even within the same second or by a tool
+/// which does not update the timestamp. Every world
+/// gets a directory of its own, named after the hash of its canonical path,
+/// so worlds can share a `cache_dir`.
+pub(crate) struct ChunkCache {
+    dir: PathBuf,
+}
+
+/// All non-air blocks of a chunk. Positions are packed as `y << 8 | z << 4 | x`
+/// relative to the chunk and point into `palette` through `states`. Biome cells
+/// are stored the same way, packed as `y << 4 | z << 2 | x` in cells.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedChunk {
+    timestamp: i64,
+    fingerprint: i64,
+    palette: Vec<BlockState>,
+    positions: Vec<i32>,
+    states: Vec<i32>,
+    biome_palette: Vec<String>,
+    biome_positions: Vec<i32>,
+    biome_states: Vec<i32>,
+}
+
+impl ChunkCache {
+    pub(crate) fn for_region(
+        cache_dir: &str,
+        lvl_path: &Path,
+        file_x: i32,
+        file_z: i32,
+    ) -> Result<ChunkCache> {
+        let lvl_path = fs::canonicalize(lvl_path)
+            .context(format!("Cannot resolve level path {}", lvl_path.display()))?;
+        let mut hasher = FxHasher::default();
+        lvl_path.hash(&mut hasher);
+        let dir = PathBuf::from(cache_dir)
+            .join(format!("{:016x}", hasher.finish()))
+            .join(format!("r.{file_x}.{file_z}"));
+        fs::create_dir_all(&dir).context(format!("Cannot create cache dir {}", dir.display()))?;
+
+        Ok(ChunkCache { dir })
+    }
+
+    pub(crate) fn load(
+        &self,
+        chunk_x: usize,
+        chunk_z: usize,
+        timestamp: u32,
+        fingerprint: u64,
+    ) -> Option<CachedChunk> {
+        let bytes = fs::read(self.chunk_path(chunk_x, chunk_z)).ok()?;
+        let cached: CachedChunk = from_bytes(&bytes).ok()?;
+
+        (cached.timestamp == i64::from(timestamp)
+            && cached.fingerprint == fingerprint.cast_signed())
+        .then_some(cached)
+    }
+
+    /// Writes the entry to a temporary file first and renames it, so a reader
+    /// never sees a partially written entry.
+    pub(crate) fn store(&self, chunk_x: usize, chunk_z: usize, chunk: &CachedChunk) -> Result<()> {
+        let bytes = to_bytes(chunk).context("Cannot serialize cached chunk")?;
+        let path = self.chunk_path(chunk_x, chunk_z);
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+
+        fs::write(&tmp_path, bytes)
+            .context(format!("Cannot write cache file {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path).context(format!("Cannot write cache file {}", path.display()))
+    }
+
+    fn chunk_path(&self, chunk_x: usize, chunk_z: usize) -> PathBuf {
+        self.dir.join(format!("c.{chunk_x}.{chunk_z}.nbt"))
+    }
+}
+
+impl CachedChunk {
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn from_chunk(chunk: &Chunk, timestamp: u32, fingerprint: u64) -> CachedChunk {
+        let mut palette_indexes: FxHashMap<&BlockState, i32> = FxHashMap::default();
+        let mut cached = CachedChunk {
+            timestamp: i64::from(timestamp),
+            fingerprint: fingerprint.cast_signed(),
+            palette: vec![],
+            positions: vec![],
+            states: vec![],
+            biome_palette: vec![],
+            biome_positions: vec![],
+            biome_states: vec![],
+        };
+
+        for (x, y, z, block) in chunk.blocks() {
+            if block.name == "minecraft:air" {
+                continue;
+            }
+
+            let state = *palette_indexes.entry(block).or_insert_with(|| {
+                cached.palette.push(block.clone());
+                cached.palette.len() as i32 - 1
+            });
+            cached
+                .positions
+                .push((i32::from(y) << 8) | ((z as i32) << 4) | x as i32);
+            cached.states.push(state);
+        }
+
+        let mut biome_indexes: FxHashMap<&str, i32> = FxHashMap::default();
+        for (x, y, z, biome) in chunk.biome_cells() {
+            let state = *biome_indexes.entry(biome).or_insert_with(|| {
+                cached.biome_palette.push(biome.to_owned());
+                cached.biome_palette.len() as i32 - 1
+            });
+            cached
+                .biome_positions
+                .push((i32::from(y) << 4) | ((z as i32) << 2) | x as i32);
+            cached.biome_states.push(state);
+        }
+
+        cached
+    }
+
+    /// Iterates over `(x, y, z, block)` with `x` and `z` relative to the chunk.
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = (usize, i16, usize, &BlockState)> + '_ {
+        self.positions
+            .iter()
+            .zip(&self.states)
+            .filter_map(|(position, state)| {
+                let block = self.palette.get(usize::try_from(*state).ok()?)?;
+                let x = (position & 0xF) as usize;
+                let z = ((position >> 4) & 0xF) as usize;
+                let y = (position >> 8) as i16;
+
+                Some((x, y, z, block))
+            })
+    }
+    /// Iterates over `(x, y, z, biome)` in cells, like [`Chunk::biome_cells`].
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    pub(crate) fn biome_cells(&self) -> impl Iterator<Item = (usize, i16, usize, &str)> + '_ {
+        self.biome_positions
+            .iter()
+            .zip(&self.biome_states)
+            .filter_map(|(position, state)| {
+                let biome = self.biome_palette.get(usize::try_from(*state).ok()?)?;
+                let x = (position & 0b11) as usize;
+                let z = ((position >> 2) & 0b11) as usize;
+                let y = (position >> 4) as i16;
+
+                Some((x, y, z, biome.as_str()))
+            })
+    }
+}
@@ -0,0 +1,367 @@
+use anyhow::{ensure, Context, Result};
+use fastnbt::{from_bytes, ByteArray, LongArray};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub(crate) const SECTION_SIZE: usize = 16;
+const SECTION_BLOCKS: usize = SECTION_SIZE * SECTION_SIZE * SECTION_SIZE;
+/// Biomes are stored per cell of 4×4×4 blocks.
+pub(crate) const BIOME_CELL_SIZE: usize = 4;
+const SECTION_CELLS: usize = SECTION_SIZE / BIOME_CELL_SIZE;
+/// First data version with sections at the root of the chunk (21w43a).
+const MIN_DATA_VERSION: i32 = 2844;
+
+/// Chunk of the 1.18+ Anvil format. Unlike `fastanvil`, every index taken from
+/// the chunk data is bounds checked, so malformed chunks never panic.
+pub(crate) struct Chunk {
+    sections: Vec<Section>,
+    min_section_y: i32,
+    /// Index into `sections` for every section y starting at `min_section_y`.
+    lookup: Vec<Option<usize>>,
+}
+
+#[derive(Deserialize)]
+struct RawChunk {
+    #[serde(rename = "DataVersion")]
+    data_version: i32,
+    #[serde(default)]
+    sections: Vec<Section>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Section {
+    #[serde(rename = "Y")]
+    y: i8,
+    block_states: Option<PalettedData<BlockState>>,
+    biomes: Option<PalettedData<String>>,
+    #[serde(rename = "BlockLight")]
+    block_light: Option<ByteArray>,
+    #[serde(rename = "SkyLight")]
+    sky_light: Option<ByteArray>,
+}
+
+#[derive(Deserialize)]
+struct PalettedData<T> {
+    palette: Vec<T>,
+    data: Option<LongArray>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BlockState {
+    #[serde(rename = "Name")]
+    pub(crate) name: String,
+    #[serde(
+        rename = "Properties",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub(crate) properties: BTreeMap<String, String>,
+}
+
+impl Chunk {
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Chunk> {
+        let raw: RawChunk = from_bytes(data).context("Cannot parse chunk data.")?;
+        ensure!(
+            raw.data_version >= MIN_DATA_VERSION,
+            "Chunks older than 1.18 are not supported, data version {}",
+            raw.data_version
+        );
+
+        let min_section_y = raw.sections.iter().map(|s| i32::from(s.y)).min();
+        let max_section_y = raw.sections.iter().map(|s| i32::from(s.y)).max();
+        let mut lookup = vec![];
+        if let (Some(min), Some(max)) = (min_section_y, max_section_y) {
+            lookup = vec![None; (max - min + 1) as usize];
+            for (index, section) in raw.sections.iter().enumerate() {
+                lookup[(i32::from(section.y) - min) as usize] = Some(index);
+            }
+        }
+
+        Ok(Chunk {
+            sections: raw.sections,
+            min_section_y: min_section_y.unwrap_or(0),
+            lookup,
+        })
+    }
+
+    /// Section holding the blocks of `y`, `None` if the chunk has no such section.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn section(&self, y: i16) -> Option<&Section> {
+        let index =
+            usize::try_from(i32::from(y).div_euclid(SECTION_SIZE as i32) - self.min_section_y)
+                .ok()?;
+
+        self.sections.get((*self.lookup.get(index)?)?)
+    }
+
+    /// Sky light at `x` and `z` relative to the chunk in the bottom layer of the
+    /// nearest section above `y` with a `SkyLight` array, which the game uses
+    /// for sections saved without one. `None` above the topmost such section.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn sky_light_above(&self, x: usize, y: i16, z: usize) -> Option<u8> {
+        if x >= SECTION_SIZE || z >= SECTION_SIZE {
+            return None;
+        }
+        let above = i32::from(y).div_euclid(SECTION_SIZE as i32) - self.min_section_y + 1;
+        let sky_light = self
+            .lookup
+            .iter()
+            .skip(usize::try_from(above).unwrap_or(0))
+            .flatten()
+            .find_map(|index| self.sections.get(*index)?.sky_light.as_ref());
+
+        light_nibble(sky_light, z * SECTION_SIZE + x)
+    }
+
+    /// Iterates over `(x, y, z, block)` of every block stored in the chunk, with
+    /// `x` and `z` relative to the chunk.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = (usize, i16, usize, &BlockState)> + '_ {
+        self.sections.iter().flat_map(|section| {
+            let min_y = i16::from(section.y) * SECTION_SIZE as i16;
+
+            (0..SECTION_BLOCKS).filter_map(move |index| {
+                let block = section.block_states.as_ref()?.get(index, 4)?;
+                let x = index % SECTION_SIZE;
+                let z = (index / SECTION_SIZE) % SECTION_SIZE;
+                let y = min_y + (index / (SECTION_SIZE * SECTION_SIZE)) as i16;
+
+                Some((x, y, z, block))
+            })
+        })
+    }
+}
+
+impl Chunk {
+    /// Iterates over `(x, y, z, biome)` of every biome cell stored in the
+    /// chunk, in cells relative to the chunk on `x` and `z` and absolute on `y`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn biome_cells(&self) -> impl Iterator<Item = (usize, i16, usize, &str)> + '_ {
+        let cells = SECTION_CELLS * SECTION_CELLS * SECTION_CELLS;
+
+        self.sections.iter().flat_map(move |section| {
+            let min_y = i16::from(section.y) * SECTION_CELLS as i16;
+
+            (0..cells).filter_map(move |index| {
+                let biome = section.biomes.as_ref()?.get(index, 0)?;
+                let x = index % SECTION_CELLS;
+                let z = (index / SECTION_CELLS) % SECTION_CELLS;
+                let y = min_y + (index / (SECTION_CELLS * SECTION_CELLS)) as i16;
+
+                Some((x, y, z, biome.as_str()))
+            })
+        })
+    }
+}
+
+impl Section {
+    /// Block at `x` and `z` relative to the chunk and the absolute `y`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn block(&self, x: usize, y: i16, z: usize) -> Option<&BlockState> {
+        if x >= SECTION_SIZE || z >= SECTION_SIZE {
+            return None;
+        }
+        let y = usize::try_from(y.rem_euclid(SECTION_SIZE as i16)).ok()?;
+
+        self.block_states
+            .as_ref()?
+            .get(y * SECTION_SIZE * SECTION_SIZE + z * SECTION_SIZE + x, 4)
+    }
+
+    /// Block light and sky light of the block at `x` and `z` relative to the
+    /// chunk and the absolute `y`, each `None` if the section has no such array.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn light(&self, x: usize, y: i16, z: usize) -> (Option<u8>, Option<u8>) {
+        if x >= SECTION_SIZE || z >= SECTION_SIZE {
+            return (None, None);
+        }
+        let y = y.rem_euclid(SECTION_SIZE as i16) as usize;
+        let index = y * SECTION_SIZE * SECTION_SIZE + z * SECTION_SIZE + x;
+
+        (
+            light_nibble(self.block_light.as_ref(), index),
+            light_nibble(self.sky_light.as_ref(), index),
+        )
+    }
+
+    /// Biome of the block at `x` and `z` relative to the chunk and the absolute `y`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn biome(&self, x: usize, y: i16, z: usize) -> Option<&str> {
+        if x >= SECTION_SIZE || z >= SECTION_SIZE {
+            return None;
+        }
+        let y = usize::try_from(y.rem_euclid(SECTION_SIZE as i16)).ok()?;
+        let cell = |c: usize| c / BIOME_CELL_SIZE;
+
+        self.biomes
+            .as_ref()?
+            .get(
+                cell(y) * SECTION_CELLS * SECTION_CELLS + cell(z) * SECTION_CELLS + cell(x),
+                0,
+            )
+            .map(String::as_str)
+    }
+}
+
+/// Light level of the block at `index` of a section, stored as one nibble per
+/// block with the lower nibble first.
+fn light_nibble(light: Option<&ByteArray>, index: usize) -> Option<u8> {
+    let byte = light?.get(index / 2)?.cast_unsigned();
+
+    Some(if index.is_multiple_of(2) {
+        byte & 0xF
+    } else {
+        byte >> 4
+    })
+}
+
+impl<T> PalettedData<T> {
+    /// Palette entry of the value at `index`. Values are packed into longs with
+    /// at least `min_bits` bits each and never span two longs.
+    #[allow(clippy::cast_sign_loss)]
+    fn get(&self, index: usize, min_bits: usize) -> Option<&T> {
+        let Some(data) = &self.data else {
+            return if self.palette.len() == 1 {
+                self.palette.first()
+            } else {
+                None
+            };
+        };
+
+        let needed_bits =
+            (usize::BITS - self.palette.len().saturating_sub(1).leading_zeros()) as usize;
+        let bits = needed_bits.max(min_bits).max(1);
+        if bits > 32 {
+            return None;
+        }
+        let per_long = 64 / bits;
+        let long = *data.get(index / per_long)? as u64;
+        let palette_index = (long >> ((index % per_long) * bits)) & ((1 << bits) - 1);
+
+        self.palette.get(usize::try_from(palette_index).ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastnbt::{nbt, to_bytes};
+
+    impl Chunk {
+        fn block(&self, x: usize, y: i16, z: usize) -> Option<&BlockState> {
+            self.section(y)?.block(x, y, z)
+        }
+    }
+
+    fn chunk(sections: &fastnbt::Value) -> Result<Chunk> {
+        Chunk::from_bytes(
+            &to_bytes(&nbt!({
+                "DataVersion": 2975,
+                "sections": sections.clone(),
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn read_packed_blocks() {
+        // 4 bits per block, the second block of the section is stone
+        let chunk = chunk(&nbt!([{
+            "Y": -1_i8,
+            "block_states": {
+                "palette": [{"Name": "minecraft:air"}, {"Name": "minecraft:stone"}],
+                "data": fastnbt::LongArray::new(vec![0x10; 256]),
+            },
+        }]))
+        .unwrap();
+
+        assert_eq!(chunk.block(1, -16, 0).unwrap().name, "minecraft:stone");
+        assert_eq!(chunk.block(0, -16, 0).unwrap().name, "minecraft:air");
+        assert_eq!(chunk.block(1, -15, 3).unwrap().name, "minecraft:stone");
+        assert_eq!(chunk.block(2, -15, 3).unwrap().name, "minecraft:air");
+        assert!(chunk.block(1, 0, 0).is_none());
+        assert!(chunk.block(16, -16, 0).is_none());
+        assert_eq!(
+            chunk
+                .blocks()
+                .filter(|(.., block)| block.name == "minecraft:stone")
+                .count(),
+            256
+        );
+    }
+    #[test]
+    fn malformed_sections_have_no_blocks() {
+        let chunk = chunk(&nbt!([
+            {
+                "Y": 0_i8,
+                "block_states": {
+                    "palette": [{"Name": "minecraft:air"}, {"Name": "minecraft:stone"}],
+                    "data": fastnbt::LongArray::new(vec![-1; 3]),
+                },
+            },
+            {
+                "Y": 1_i8,
+                "block_states": {"palette": [], "data": fastnbt::LongArray::new(vec![1])},
+            },
+        ]))
+        .unwrap();
+
+        assert!(chunk.block(0, 0, 0).is_none());
+        assert!(chunk.block(0, 15, 15).is_none());
+        assert!(chunk.block(0, 16, 0).is_none());
+        assert_eq!(chunk.blocks().count(), 0);
+    }
+    #[test]
+    fn read_light_nibbles() {
+        // the lower nibble holds the even block index
+        let chunk = chunk(&nbt!([{
+            "Y": 0_i8,
+            "block_states": {"palette": [{"Name": "minecraft:air"}]},
+            "BlockLight": fastnbt::ByteArray::new(vec![0x5A; 2048]),
+        }]))
+        .unwrap();
+        let section = chunk.section(0).unwrap();
+
+        assert_eq!(section.light(0, 0, 0), (Some(0xA), None));
+        assert_eq!(section.light(1, 15, 0), (Some(0x5), None));
+        assert_eq!(section.light(16, 0, 0), (None, None));
+    }
+    #[test]
+    fn read_biomes() {
+        // 1 bit per cell, the second cell of every long is a desert
+        let chunk = chunk(&nbt!([
+            {
+                "Y": 0_i8,
+                "biomes": {
+                    "palette": ["minecraft:plains", "minecraft:desert"],
+                    "data": fastnbt::LongArray::new(vec![0b10]),
+                },
+            },
+            {"Y": 1_i8, "biomes": {"palette": ["minecraft:ocean"]}},
+        ]))
+        .unwrap();
+        let biome = |x, y, z| chunk.section(y)?.biome(x, y, z);
+
+        assert_eq!(biome(0, 0, 0), Some("minecraft:plains"));
+        assert_eq!(biome(4, 3, 3), Some("minecraft:desert"));
+        assert_eq!(biome(8, 0, 0), Some("minecraft:plains"));
+        assert_eq!(biome(15, 31, 15), Some("minecraft:ocean"));
+        assert_eq!(biome(16, 0, 0), None);
+        assert_eq!(
+            chunk
+                .biome_cells()
+                .filter(|(.., biome)| *biome == "minecraft:desert")
+                .collect::<Vec<_>>(),
+            vec![(1, 0, 0, "minecraft:desert")]
+        );
+        assert_eq!(chunk.biome_cells().count(), 128);
+    }
+    #[test]
+    fn reject_old_chunks() {
+        let data = to_bytes(&nbt!({"DataVersion": 1343, "Level": {}})).unwrap();
+
+        assert!(Chunk::from_bytes(&data).is_err());
+        assert!(Chunk::from_bytes(&[10, 0]).is_err());
+    }
+}
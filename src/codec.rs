@@ -0,0 +1,446 @@
+//! Compact binary encoding of cuboids.
+//!
+//! A stream starts with the magic `MCCB`, the codec version and a flags byte
+//! (`1` palette, `2` zlib compressed body), followed by the origin as zigzag
+//! varints, the cuboid count and the palette. Every cuboid of the body is the
+//! zigzag varint delta of its start to the previous start (the origin for the
+//! first one), its size minus one as varints and, with a palette, the varint
+//! palette index. The body is zlib compressed if the flag is set.
+
+use crate::{BlockCoordinates, BlockSequence};
+use anyhow::{bail, ensure, Context, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use rustc_hash::FxHashMap;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 4] = b"MCCB";
+const CODEC_VERSION: u8 = 1;
+const FLAG_PALETTE: u8 = 1;
+const FLAG_COMPRESSED: u8 = 2;
+const MAX_NAME_LEN: u64 = 1024;
+
+#[derive(Clone, Default)]
+pub struct EncodeOptions {
+    /// Coordinates are stored relative to it, the minimum corner of all
+    /// cuboids if `None`.
+    pub origin: Option<BlockCoordinates>,
+    /// Compresses everything but the header with zlib.
+    pub compress: bool,
+}
+
+/// Writes `cuboids` in the binary format, without block types.
+///
+/// # Errors
+///
+/// Will return `Err` if writing to `out` fails.
+pub fn encode_cuboids(
+    out: impl Write,
+    cuboids: &[BlockSequence],
+    options: &EncodeOptions,
+) -> Result<()> {
+    encode(
+        out,
+        cuboids.iter().map(|c| (c, None)),
+        cuboids.len(),
+        &[],
+        options,
+    )
+}
+
+/// Writes `cuboids` in the binary format, storing block names in a palette.
+///
+/// # Errors
+///
+/// Will return `Err` if writing to `out` fails.
+pub fn encode_typed_cuboids(
+    out: impl Write,
+    cuboids: &[(BlockSequence, &str)],
+    options: &EncodeOptions,
+) -> Result<()> {
+    let mut palette: Vec<String> = vec![];
+    let mut indexes: FxHashMap<&str, u64> = FxHashMap::default();
+    let states: Vec<u64> = cuboids
+        .iter()
+        .map(|(_, name)| {
+            *indexes.entry(name).or_insert_with(|| {
+                palette.push((*name).to_owned());
+                palette.len() as u64 - 1
+            })
+        })
+        .collect();
+
+    encode(
+        out,
+        cuboids.iter().zip(states).map(|((c, _), s)| (c, Some(s))),
+        cuboids.len(),
+        &palette,
+        options,
+    )
+}
+
+fn encode<'a>(
+    mut out: impl Write,
+    cuboids: impl Iterator<Item = (&'a BlockSequence, Option<u64>)> + Clone,
+    count: usize,
+    palette: &[String],
+    options: &EncodeOptions,
+) -> Result<()> {
+    let origin = options.origin.clone().unwrap_or_else(|| {
+        cuboids
+            .clone()
+            .map(|(c, _)| c.start.clone())
+            .reduce(|a, b| BlockCoordinates::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)))
+            .unwrap_or_default()
+    });
+    let has_palette = !palette.is_empty();
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[
+        CODEC_VERSION,
+        (u8::from(has_palette) * FLAG_PALETTE) | (u8::from(options.compress) * FLAG_COMPRESSED),
+    ])?;
+    write_coordinates(&mut out, &BlockCoordinates::default(), &origin)?;
+    write_varint(&mut out, count as u64)?;
+    if has_palette {
+        write_varint(&mut out, palette.len() as u64)?;
+        for name in palette {
+            write_varint(&mut out, name.len() as u64)?;
+            out.write_all(name.as_bytes())?;
+        }
+    }
+
+    if options.compress {
+        let mut encoder = ZlibEncoder::new(out, Compression::default());
+        write_body(&mut encoder, cuboids, origin)?;
+        encoder.finish()?.flush()?;
+    } else {
+        write_body(&mut out, cuboids, origin)?;
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+fn write_body<'a>(
+    out: &mut impl Write,
+    cuboids: impl Iterator<Item = (&'a BlockSequence, Option<u64>)>,
+    origin: BlockCoordinates,
+) -> Result<()> {
+    let mut previous = origin;
+    for (cuboid, state) in cuboids {
+        let size = cuboid.size();
+
+        write_coordinates(out, &previous, &cuboid.start)?;
+        write_varint(out, u64::from((size.x - 1).unsigned_abs()))?;
+        write_varint(out, u64::from((size.y - 1).unsigned_abs()))?;
+        write_varint(out, u64::from((size.z - 1).unsigned_abs()))?;
+        if let Some(state) = state {
+            write_varint(out, state)?;
+        }
+        previous = cuboid.start.clone();
+    }
+
+    Ok(())
+}
+
+/// Streaming reader of the binary format, yielding every cuboid with the
+/// palette index of its block.
+pub struct CuboidReader<R: Read> {
+    body: Body<R>,
+    palette: Vec<String>,
+    has_palette: bool,
+    remaining: u64,
+    previous: BlockCoordinates,
+}
+
+enum Body<R: Read> {
+    Plain(R),
+    Compressed(ZlibDecoder<R>),
+}
+
+impl<R: Read> CuboidReader<R> {
+    /// Reads the header, the cuboids are read lazily while iterating.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `reader` does not start with a valid header.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(mut reader: R) -> Result<CuboidReader<R>> {
+        let mut header = [0u8; 6];
+        reader
+            .read_exact(&mut header)
+            .context("Cannot read cuboid stream header")?;
+        ensure!(&header[..4] == MAGIC, "Not a cuboid stream");
+        ensure!(
+            header[4] == CODEC_VERSION,
+            "Unsupported cuboid stream version {}",
+            header[4]
+        );
+        let flags = header[5];
+
+        let origin = read_coordinates(&mut reader, &BlockCoordinates::default())?;
+        let remaining = read_varint(&mut reader)?;
+        let has_palette = flags & FLAG_PALETTE != 0;
+        let mut palette = vec![];
+        if has_palette {
+            for _ in 0..read_varint(&mut reader)? {
+                let len = read_varint(&mut reader)?;
+                ensure!(len <= MAX_NAME_LEN, "Block name is too long");
+                let mut name = vec![0u8; len as usize];
+                reader.read_exact(&mut name)?;
+                palette.push(String::from_utf8(name).context("Block name is not utf-8")?);
+            }
+        }
+
+        let body = if flags & FLAG_COMPRESSED == 0 {
+            Body::Plain(reader)
+        } else {
+            Body::Compressed(ZlibDecoder::new(reader))
+        };
+
+        Ok(CuboidReader {
+            body,
+            palette,
+            has_palette,
+            remaining,
+            previous: origin,
+        })
+    }
+
+    /// Block names the palette indexes point into, empty for untyped streams.
+    #[must_use]
+    pub fn palette(&self) -> &[String] {
+        &self.palette
+    }
+
+    /// Number of cuboids not read yet.
+    #[must_use]
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    fn read_cuboid(&mut self) -> Result<(BlockSequence, Option<usize>)> {
+        let body: &mut dyn Read = match &mut self.body {
+            Body::Plain(reader) => reader,
+            Body::Compressed(reader) => reader,
+        };
+        let start = read_coordinates(body, &self.previous)?;
+        let size_x = i32::try_from(read_varint(body)?)?;
+        let size_y = i16::try_from(read_varint(body)?)?;
+        let size_z = i32::try_from(read_varint(body)?)?;
+        let end = BlockCoordinates::new(
+            start
+                .x
+                .checked_add(size_x)
+                .context("Cuboid is out of range")?,
+            start
+                .y
+                .checked_add(size_y)
+                .context("Cuboid is out of range")?,
+            start
+                .z
+                .checked_add(size_z)
+                .context("Cuboid is out of range")?,
+        );
+
+        let state = if self.has_palette {
+            let index = usize::try_from(read_varint(body)?)?;
+            ensure!(
+                index < self.palette.len(),
+                "Palette index {index} is out of range"
+            );
+            Some(index)
+        } else {
+            None
+        };
+        self.previous = start.clone();
+
+        Ok((BlockSequence::new(start, end), state))
+    }
+}
+
+impl<R: Read> Iterator for CuboidReader<R> {
+    type Item = Result<(BlockSequence, Option<usize>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let cuboid = self.read_cuboid();
+        if cuboid.is_err() {
+            self.remaining = 0;
+        }
+        Some(cuboid)
+    }
+}
+
+/// Reads every cuboid of a stream, dropping the block types.
+///
+/// # Errors
+///
+/// Will return `Err` if the stream is malformed.
+pub fn decode_cuboids(reader: impl Read) -> Result<Vec<BlockSequence>> {
+    CuboidReader::new(reader)?
+        .map(|cuboid| cuboid.map(|(cuboid, _)| cuboid))
+        .collect()
+}
+
+fn write_coordinates(
+    out: &mut impl Write,
+    from: &BlockCoordinates,
+    to: &BlockCoordinates,
+) -> Result<()> {
+    write_varint(out, zigzag(i64::from(to.x) - i64::from(from.x)))?;
+    write_varint(out, zigzag(i64::from(to.y) - i64::from(from.y)))?;
+    write_varint(out, zigzag(i64::from(to.z) - i64::from(from.z)))
+}
+
+fn read_coordinates(
+    reader: &mut (impl Read + ?Sized),
+    from: &BlockCoordinates,
+) -> Result<BlockCoordinates> {
+    let mut coordinate = |from: i64| -> Result<i64> {
+        from.checked_add(unzigzag(read_varint(reader)?))
+            .context("Coordinate is out of range")
+    };
+
+    Ok(BlockCoordinates::new(
+        i32::try_from(coordinate(i64::from(from.x))?)?,
+        i16::try_from(coordinate(i64::from(from.y))?)?,
+        i32::try_from(coordinate(i64::from(from.z))?)?,
+    ))
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn write_varint(out: &mut impl Write, mut value: u64) -> Result<()> {
+    let mut bytes = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+
+    out.write_all(&bytes[..len])?;
+    Ok(())
+}
+
+fn read_varint(reader: &mut (impl Read + ?Sized)) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7F) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    bail!("Varint is too long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cuboids() -> Vec<BlockSequence> {
+        let b = BlockCoordinates::new;
+        vec![
+            BlockSequence::new(b(-100, -64, -100), b(100, -60, 100)),
+            BlockSequence::new(b(5, 10, -3), b(5, 10, -3)),
+            BlockSequence::new(b(i32::MIN, 319, i32::MAX), b(i32::MIN, 319, i32::MAX)),
+        ]
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            let mut out = vec![];
+            write_varint(&mut out, value).unwrap();
+            assert_eq!(read_varint(&mut out.as_slice()).unwrap(), value);
+        }
+        for value in [0, -1, 1, i64::MIN, i64::MAX] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert!(read_varint(&mut [0xFF; 11].as_slice()).is_err());
+    }
+    #[test]
+    fn round_trip() {
+        for compress in [false, true] {
+            let mut out = vec![];
+            let options = EncodeOptions {
+                compress,
+                ..Default::default()
+            };
+            encode_cuboids(&mut out, &cuboids(), &options).unwrap();
+
+            assert_eq!(decode_cuboids(out.as_slice()).unwrap(), cuboids());
+        }
+    }
+    #[test]
+    fn round_trip_typed() {
+        let typed: Vec<(BlockSequence, &str)> = cuboids()
+            .into_iter()
+            .zip(["minecraft:stone", "minecraft:dirt", "minecraft:stone"])
+            .collect();
+        let mut out = vec![];
+        encode_typed_cuboids(&mut out, &typed, &EncodeOptions::default()).unwrap();
+
+        let reader = CuboidReader::new(out.as_slice()).unwrap();
+        assert_eq!(reader.palette(), ["minecraft:stone", "minecraft:dirt"]);
+        assert_eq!(reader.remaining(), 3);
+        let decoded: Vec<(BlockSequence, Option<usize>)> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(
+            decoded.iter().map(|(_, s)| *s).collect::<Vec<_>>(),
+            vec![Some(0), Some(1), Some(0)]
+        );
+        assert_eq!(decoded[2].0, cuboids()[2]);
+    }
+    #[test]
+    fn smaller_than_json() {
+        let cuboids: Vec<BlockSequence> = (0..1000)
+            .map(|i| {
+                BlockSequence::new(
+                    BlockCoordinates::new(i, -64, i * 2),
+                    BlockCoordinates::new(i + 3, -60, i * 2 + 1),
+                )
+            })
+            .collect();
+        let mut out = vec![];
+        encode_cuboids(&mut out, &cuboids, &EncodeOptions::default()).unwrap();
+
+        assert!(out.len() * 10 < serde_json::to_vec(&cuboids).unwrap().len());
+    }
+    #[test]
+    fn reject_malformed_streams() {
+        let mut out = vec![];
+        encode_cuboids(&mut out, &cuboids(), &EncodeOptions::default()).unwrap();
+
+        assert!(decode_cuboids(&out[..out.len() - 1]).is_err());
+        assert!(decode_cuboids(&b"MCCB\x09\x00"[..]).is_err());
+        assert!(decode_cuboids(&b"JSON"[..]).is_err());
+        // y of the start overflows
+        assert!(decode_cuboids(
+            &b"MCCB\x01\x00\x00\x00\x00\x01\x00\xFF\xFF\x07\x00\x00\x00\x00"[..]
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::chunk::SECTION_SIZE;
+use crate::{BlockCoordinates, BlockSequence};
+use parry3d::math::{Isometry, Real};
+use parry3d::shape::SharedShape;
+use std::collections::BTreeMap;
+
+type PositionedShape = (Isometry<Real>, SharedShape);
+
+/// Placement of the cuboids in the physics world.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColliderOptions {
+    /// Size of a block in world units.
+    pub scale: Real,
+    /// World position of the corner of the block `(0, 0, 0)`.
+    pub offset: [Real; 3],
+}
+
+impl Default for ColliderOptions {
+    fn default() -> Self {
+        ColliderOptions {
+            scale: 1.0,
+            offset: [0.0; 3],
+        }
+    }
+}
+
+impl ColliderOptions {
+    /// Position of the block corner `origin` in the world.
+    #[allow(clippy::cast_precision_loss)]
+    fn isometry(&self, origin: &BlockCoordinates) -> Isometry<Real> {
+        let [x, y, z] = self.offset;
+
+        Isometry::translation(
+            x + origin.x as Real * self.scale,
+            y + Real::from(origin.y) * self.scale,
+            z + origin.z as Real * self.scale,
+        )
+    }
+
+    /// Box of `cuboid`, positioned relative to the block corner `origin`.
+    /// Coordinates are made relative before the conversion to floats, so
+    /// cuboids far away from the world origin keep their precision.
+    fn shape(&self, cuboid: &BlockSequence, origin: &BlockCoordinates) -> PositionedShape {
+        let local = BlockSequence::new(
+            BlockCoordinates::new(
+                cuboid.start.x - origin.x,
+                cuboid.start.y - origin.y,
+                cuboid.start.z - origin.z,
+            ),
+            BlockCoordinates::new(
+                cuboid.end.x - origin.x,
+                cuboid.end.y - origin.y,
+                cuboid.end.z - origin.z,
+            ),
+        );
+        let [cx, cy, cz] = local.center().map(|c| c * self.scale);
+        let [hx, hy, hz] = local.half_extents().map(|h| h * self.scale);
+
+        (
+            Isometry::translation(cx, cy, cz),
+            SharedShape::cuboid(hx, hy, hz),
+        )
+    }
+}
+
+/// One positioned box per cuboid.
+#[must_use]
+pub fn cuboid_shapes(cuboids: &[BlockSequence], options: &ColliderOptions) -> Vec<PositionedShape> {
+    let origin = BlockCoordinates::default();
+    let world = options.isometry(&origin);
+
+    cuboids
+        .iter()
+        .map(|cuboid| {
+            let (position, shape) = options.shape(cuboid, &origin);
+            (world * position, shape)
+        })
+        .collect()
+}
+
+/// All cuboids as a single compound shape, `None` if there are no cuboids.
+#[must_use]
+pub fn compound_shape(
+    cuboids: &[BlockSequence],
+    options: &ColliderOptions,
+) -> Option<PositionedShape> {
+    let origin = BlockCoordinates::default();
+    let shapes: Vec<_> = cuboids
+        .iter()
+        .map(|cuboid| options.shape(cuboid, &origin))
+        .collect();
+
+    (!shapes.is_empty()).then(|| (options.isometry(&origin), SharedShape::compound(shapes)))
+}
+
+/// One compound shape per chunk column, keyed by the chunk x and z. Cuboids
+/// are cut at chunk borders, so every compound covers exactly the blocks of
+/// its chunk and can be replaced once the chunk changes.
+#[must_use]
+pub fn chunk_compound_shapes(
+    cuboids: &[BlockSequence],
+    options: &ColliderOptions,
+) -> Vec<((i32, i32), Isometry<Real>, SharedShape)> {
+    let mut chunks: BTreeMap<(i32, i32), Vec<PositionedShape>> = BTreeMap::new();
+
+    for cuboid in cuboids {
+        for (chunk, part) in split_by_chunks(cuboid) {
+            let shape = options.shape(&part, &chunk_origin(chunk));
+            chunks.entry(chunk).or_default().push(shape);
+        }
+    }
+
+    chunks
+        .into_iter()
+        .map(|(chunk, shapes)| {
+            (
+                chunk,
+                options.isometry(&chunk_origin(chunk)),
+                SharedShape::compound(shapes),
+            )
+        })
+        .collect()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn chunk_origin((chunk_x, chunk_z): (i32, i32)) -> BlockCoordinates {
+    let size = SECTION_SIZE as i32;
+
+    BlockCoordinates::new(chunk_x * size, 0, chunk_z * size)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn split_by_chunks(cuboid: &BlockSequence) -> Vec<((i32, i32), BlockSequence)> {
+    let size = SECTION_SIZE as i32;
+    let mut parts = vec![];
+
+    for chunk_z in cuboid.start.z.div_euclid(size)..=cuboid.end.z.div_euclid(size) {
+        for chunk_x in cuboid.start.x.div_euclid(size)..=cuboid.end.x.div_euclid(size) {
+            let chunk = BlockSequence::new(
+                BlockCoordinates::new(chunk_x * size, cuboid.start.y, chunk_z * size),
+                BlockCoordinates::new(
+                    chunk_x * size + size - 1,
+                    cuboid.end.y,
+                    chunk_z * size + size - 1,
+                ),
+            );
+            if let Some(part) = cuboid.intersection(&chunk) {
+                parts.push(((chunk_x, chunk_z), part));
+            }
+        }
+    }
+
+    parts
+}
+
+#[cfg(feature = "rapier")]
+pub use self::rapier::{chunk_colliders, colliders, compound_collider};
+
+#[cfg(feature = "rapier")]
+mod rapier {
+    use super::{chunk_compound_shapes, compound_shape, cuboid_shapes, ColliderOptions};
+    use crate::BlockSequence;
+    use rapier3d::geometry::{Collider, ColliderBuilder};
+
+    /// One fixed cuboid collider per cuboid.
+    #[must_use]
+    pub fn colliders(cuboids: &[BlockSequence], options: &ColliderOptions) -> Vec<Collider> {
+        cuboid_shapes(cuboids, options)
+            .into_iter()
+            .map(|(position, shape)| ColliderBuilder::new(shape).position(position).build())
+            .collect()
+    }
+
+    /// All cuboids as a single compound collider, `None` if there are no cuboids.
+    #[must_use]
+    pub fn compound_collider(
+        cuboids: &[BlockSequence],
+        options: &ColliderOptions,
+    ) -> Option<Collider> {
+        compound_shape(cuboids, options)
+            .map(|(position, shape)| ColliderBuilder::new(shape).position(position).build())
+    }
+
+    /// One compound collider per chunk column, see [`chunk_compound_shapes`].
+    #[must_use]
+    pub fn chunk_colliders(
+        cuboids: &[BlockSequence],
+        options: &ColliderOptions,
+    ) -> Vec<((i32, i32), Collider)> {
+        chunk_compound_shapes(cuboids, options)
+            .into_iter()
+            .map(|(chunk, position, shape)| {
+                (
+                    chunk,
+                    ColliderBuilder::new(shape).position(position).build(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parry3d::math::Point;
+
+    fn cuboid(start: (i32, i16, i32), end: (i32, i16, i32)) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(start.0, start.1, start.2),
+            BlockCoordinates::new(end.0, end.1, end.2),
+        )
+    }
+
+    #[test]
+    fn cuboid_shapes_scaled_and_offset() {
+        let options = ColliderOptions {
+            scale: 0.5,
+            offset: [10.0, 0.0, -10.0],
+        };
+        let shapes = cuboid_shapes(&[cuboid((0, 0, 0), (3, 1, 0))], &options);
+        let (position, shape) = &shapes[0];
+        let aabb = shape.compute_aabb(position);
+
+        assert_eq!(aabb.mins, Point::new(10.0, 0.0, -10.0));
+        assert_eq!(aabb.maxs, Point::new(12.0, 1.0, -9.5));
+    }
+    #[test]
+    fn compound_shape_bounds() {
+        assert!(compound_shape(&[], &ColliderOptions::default()).is_none());
+
+        let (position, shape) = compound_shape(
+            &[
+                cuboid((-2, 0, 0), (-1, 0, 0)),
+                cuboid((0, -64, 5), (0, 10, 5)),
+            ],
+            &ColliderOptions::default(),
+        )
+        .unwrap();
+        let aabb = shape.compute_aabb(&position);
+
+        assert_eq!(aabb.mins, Point::new(-2.0, -64.0, 0.0));
+        assert_eq!(aabb.maxs, Point::new(1.0, 11.0, 6.0));
+    }
+    #[test]
+    fn chunk_compound_shapes_split_at_borders() {
+        let shapes = chunk_compound_shapes(
+            &[cuboid((-2, 0, 0), (17, 0, 0))],
+            &ColliderOptions::default(),
+        );
+        let chunks: Vec<(i32, i32)> = shapes.iter().map(|(chunk, _, _)| *chunk).collect();
+        assert_eq!(chunks, vec![(-1, 0), (0, 0), (1, 0)]);
+
+        let (_, position, shape) = &shapes[1];
+        let aabb = shape.compute_aabb(position);
+        assert_eq!(aabb.mins, Point::new(0.0, 0.0, 0.0));
+        assert_eq!(aabb.maxs, Point::new(16.0, 1.0, 1.0));
+    }
+    #[test]
+    fn split_by_chunks_far_away() {
+        let parts = split_by_chunks(&cuboid(
+            (1_000_000, 5, -1_000_001),
+            (1_000_000, 5, -1_000_000),
+        ));
+
+        assert_eq!(
+            parts,
+            vec![
+                (
+                    (62_500, -62_501),
+                    cuboid((1_000_000, 5, -1_000_001), (1_000_000, 5, -1_000_001))
+                ),
+                (
+                    (62_500, -62_500),
+                    cuboid((1_000_000, 5, -1_000_000), (1_000_000, 5, -1_000_000))
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use crate::block_sequence::BlockSequence;
+use crate::BlockCoordinates;
+
+const LEAF_SIZE: usize = 4;
+
+/// Bounding volume hierarchy over exported cuboids.
+///
+/// Cuboids are treated as solid boxes spanning `start..end + 1` on every axis,
+/// so the block `(0, 0, 0)` occupies the unit cube between `0.0` and `1.0`.
+#[derive(Debug, Default, Clone)]
+pub struct CuboidIndex {
+    cuboids: Vec<BlockSequence>,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    bounds: Bounds,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    Leaf { first: usize, len: usize },
+    Branch { left: usize, right: usize },
+}
+
+/// Inclusive block bounds of a node.
+#[derive(Debug, Clone)]
+struct Bounds {
+    min: [i32; 3],
+    max: [i32; 3],
+}
+
+impl CuboidIndex {
+    #[must_use]
+    pub fn new(mut cuboids: Vec<BlockSequence>) -> CuboidIndex {
+        let mut nodes = vec![];
+        if !cuboids.is_empty() {
+            let len = cuboids.len();
+            build(&mut cuboids, &mut nodes, 0, len);
+        }
+
+        CuboidIndex { cuboids, nodes }
+    }
+
+    #[must_use]
+    pub fn cuboids(&self) -> &[BlockSequence] {
+        &self.cuboids
+    }
+
+    #[must_use]
+    pub fn into_cuboids(self) -> Vec<BlockSequence> {
+        self.cuboids
+    }
+
+    /// Cuboids which share at least one block with `area`.
+    #[must_use]
+    pub fn intersecting(&self, area: &BlockSequence) -> Vec<&BlockSequence> {
+        let area = Bounds::of(area);
+        self.visit(
+            vec![],
+            |_, bounds| bounds.intersects(&area),
+            |found, cuboid| {
+                if Bounds::of(cuboid).intersects(&area) {
+                    found.push(cuboid);
+                }
+            },
+        )
+    }
+
+    /// The cuboid covering `point`, if any.
+    #[must_use]
+    pub fn containing(&self, point: &BlockCoordinates) -> Option<&BlockSequence> {
+        let point = Bounds::of_block(point);
+        self.visit(
+            None,
+            |found, bounds| found.is_none() && bounds.intersects(&point),
+            |found, cuboid| {
+                if found.is_none() && Bounds::of(cuboid).intersects(&point) {
+                    *found = Some(cuboid);
+                }
+            },
+        )
+    }
+
+    /// The highest cuboid strictly below `point` in the same block column.
+    #[must_use]
+    pub fn below(&self, point: &BlockCoordinates) -> Option<&BlockSequence> {
+        let column = Bounds {
+            min: [point.x, i32::from(i16::MIN), point.z],
+            max: [point.x, i32::from(point.y) - 1, point.z],
+        };
+
+        self.intersecting(&column.to_sequence())
+            .into_iter()
+            .max_by_key(|cuboid| cuboid.end.y)
+    }
+
+    /// The first cuboid hit by a ray, together with the distance to it measured
+    /// in multiples of `direction`'s length. Cuboids containing `origin` are hit
+    /// at distance `0.0`.
+    #[must_use]
+    pub fn ray_cast(
+        &self,
+        origin: [f32; 3],
+        direction: [f32; 3],
+        max_distance: f32,
+    ) -> Option<(&BlockSequence, f32)> {
+        let closer = |best: &Option<(&BlockSequence, f32)>, t: f32| {
+            t <= max_distance && best.is_none_or(|(_, b)| t < b)
+        };
+
+        self.visit(
+            None,
+            |best, bounds| {
+                bounds
+                    .ray_entry(origin, direction)
+                    .is_some_and(|t| closer(best, t))
+            },
+            |best, cuboid| {
+                if let Some(t) = Bounds::of(cuboid).ray_entry(origin, direction) {
+                    if closer(best, t) {
+                        *best = Some((cuboid, t));
+                    }
+                }
+            },
+        )
+    }
+
+    /// The cuboid closest to `point` and the euclidean distance to its surface,
+    /// which is `0.0` when the point lies inside of it.
+    #[must_use]
+    pub fn nearest(&self, point: [f32; 3]) -> Option<(&BlockSequence, f32)> {
+        self.visit(
+            None,
+            |best: &Option<(&BlockSequence, f32)>, bounds| {
+                best.is_none_or(|(_, b)| bounds.distance_squared(point) < b * b)
+            },
+            |best, cuboid| {
+                let distance = Bounds::of(cuboid).distance_squared(point).sqrt();
+                if best.is_none_or(|(_, b)| distance < b) {
+                    *best = Some((cuboid, distance));
+                }
+            },
+        )
+    }
+
+    /// Depth-first traversal which descends only into nodes accepted by `enter`
+    /// and hands every cuboid of the reached leaves to `leaf`.
+    fn visit<'a, S>(
+        &'a self,
+        mut state: S,
+        enter: impl Fn(&S, &Bounds) -> bool,
+        mut leaf: impl FnMut(&mut S, &'a BlockSequence),
+    ) -> S {
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !enter(&state, &node.bounds) {
+                continue;
+            }
+
+            match node.kind {
+                NodeKind::Leaf { first, len } => {
+                    for cuboid in &self.cuboids[first..first + len] {
+                        leaf(&mut state, cuboid);
+                    }
+                }
+                NodeKind::Branch { left, right } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+
+        state
+    }
+}
+
+impl From<Vec<BlockSequence>> for CuboidIndex {
+    fn from(cuboids: Vec<BlockSequence>) -> Self {
+        CuboidIndex::new(cuboids)
+    }
+}
+
+/// Builds the subtree over `cuboids[first..first + len]` and returns its node index.
+fn build(cuboids: &mut [BlockSequence], nodes: &mut Vec<Node>, first: usize, len: usize) -> usize {
+    let slice = &mut cuboids[first..first + len];
+    let bounds = slice
+        .iter()
+        .map(Bounds::of)
+        .reduce(|a, b| a.union(&b))
+        .expect("nodes are never built over an empty range");
+
+    let index = nodes.len();
+    nodes.push(Node {
+        bounds: bounds.clone(),
+        kind: NodeKind::Leaf { first, len },
+    });
+    if len <= LEAF_SIZE {
+        return index;
+    }
+
+    let axis = (0..3)
+        .max_by_key(|&axis| i64::from(bounds.max[axis]) - i64::from(bounds.min[axis]))
+        .unwrap_or(0);
+    let half = len / 2;
+    slice.select_nth_unstable_by_key(half, |cuboid| Bounds::of(cuboid).doubled_center(axis));
+
+    let left = build(cuboids, nodes, first, half);
+    let right = build(cuboids, nodes, first + half, len - half);
+    nodes[index].kind = NodeKind::Branch { left, right };
+
+    index
+}
+
+impl Bounds {
+    fn of(cuboid: &BlockSequence) -> Bounds {
+        let (start, end) = (&cuboid.start, &cuboid.end);
+
+        Bounds {
+            min: [start.x, i32::from(start.y), start.z],
+            max: [end.x, i32::from(end.y), end.z],
+        }
+    }
+
+    fn of_block(block: &BlockCoordinates) -> Bounds {
+        let corner = [block.x, i32::from(block.y), block.z];
+
+        Bounds {
+            min: corner,
+            max: corner,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn to_sequence(&self) -> BlockSequence {
+        let y = |c: i32| c.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16;
+
+        BlockSequence::new(
+            BlockCoordinates::new(self.min[0], y(self.min[1]), self.min[2]),
+            BlockCoordinates::new(self.max[0], y(self.max[1]), self.max[2]),
+        )
+    }
+
+    fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: [0, 1, 2].map(|axis| self.min[axis].min(other.min[axis])),
+            max: [0, 1, 2].map(|axis| self.max[axis].max(other.max[axis])),
+        }
+    }
+
+    fn intersects(&self, other: &Bounds) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    fn doubled_center(&self, axis: usize) -> i64 {
+        i64::from(self.min[axis]) + i64::from(self.max[axis])
+    }
+
+    /// Slab test against the solid box; returns the entry distance along the ray.
+    #[allow(clippy::cast_precision_loss)]
+    fn ray_entry(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<f32> {
+        let mut t_min = 0.0_f32;
+        let mut t_max = f32::INFINITY;
+
+        for axis in 0..3 {
+            let low = self.min[axis] as f32;
+            let high = self.max[axis] as f32 + 1.0;
+
+            if direction[axis] == 0.0 {
+                if origin[axis] < low || origin[axis] > high {
+                    return None;
+                }
+                continue;
+            }
+
+            let t1 = (low - origin[axis]) / direction[axis];
+            let t2 = (high - origin[axis]) / direction[axis];
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn distance_squared(&self, point: [f32; 3]) -> f32 {
+        (0..3)
+            .map(|axis| {
+                let low = self.min[axis] as f32;
+                let high = self.max[axis] as f32 + 1.0;
+                let d = (low - point[axis]).max(point[axis] - high).max(0.0);
+
+                d * d
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cuboid(start: (i32, i16, i32), end: (i32, i16, i32)) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(start.0, start.1, start.2),
+            BlockCoordinates::new(end.0, end.1, end.2),
+        )
+    }
+
+    /// A 10x10 grid of 2x1x2 floor tiles with gaps, plus a pillar.
+    fn index() -> CuboidIndex {
+        let mut cuboids = vec![cuboid((0, 10, 0), (0, 20, 0))];
+        for x in 0..10 {
+            for z in 0..10 {
+                cuboids.push(cuboid((x * 3, 0, z * 3), (x * 3 + 1, 0, z * 3 + 1)));
+            }
+        }
+
+        CuboidIndex::new(cuboids)
+    }
+
+    #[test]
+    fn intersecting_area() {
+        let index = index();
+        let mut found = index.intersecting(&cuboid((2, 0, 2), (4, 5, 4)));
+        found.sort_by_key(|c| (c.start.x, c.start.z));
+
+        assert_eq!(found, vec![&cuboid((3, 0, 3), (4, 0, 4))]);
+        assert_eq!(index.intersecting(&cuboid((2, 0, 2), (2, 0, 2))).len(), 0);
+        assert_eq!(
+            index.intersecting(&cuboid((0, 0, 0), (29, 0, 29))).len(),
+            100
+        );
+    }
+    #[test]
+    fn containing_point() {
+        let index = index();
+
+        assert_eq!(
+            index.containing(&BlockCoordinates::new(28, 0, 1)),
+            Some(&cuboid((27, 0, 0), (28, 0, 1)))
+        );
+        assert_eq!(index.containing(&BlockCoordinates::new(29, 0, 1)), None);
+        assert_eq!(
+            index.containing(&BlockCoordinates::new(0, 15, 0)),
+            Some(&cuboid((0, 10, 0), (0, 20, 0)))
+        );
+    }
+    #[test]
+    fn below_point() {
+        let index = index();
+
+        assert_eq!(
+            index.below(&BlockCoordinates::new(0, 30, 0)),
+            Some(&cuboid((0, 10, 0), (0, 20, 0)))
+        );
+        assert_eq!(
+            index.below(&BlockCoordinates::new(1, 30, 1)),
+            Some(&cuboid((0, 0, 0), (1, 0, 1)))
+        );
+        assert_eq!(index.below(&BlockCoordinates::new(0, 5, 2)), None);
+    }
+    #[test]
+    fn ray_cast_down_and_sideways() {
+        let index = index();
+
+        let (hit, distance) = index
+            .ray_cast([1.5, 30.0, 1.5], [0.0, -1.0, 0.0], 100.0)
+            .unwrap();
+        assert_eq!(hit, &cuboid((0, 0, 0), (1, 0, 1)));
+        assert!((distance - 29.0).abs() < f32::EPSILON);
+
+        let (hit, distance) = index
+            .ray_cast([-5.0, 15.5, 0.5], [1.0, 0.0, 0.0], 100.0)
+            .unwrap();
+        assert_eq!(hit, &cuboid((0, 10, 0), (0, 20, 0)));
+        assert!((distance - 5.0).abs() < f32::EPSILON);
+
+        assert!(index
+            .ray_cast([-5.0, 15.5, 0.5], [1.0, 0.0, 0.0], 4.0)
+            .is_none());
+        assert!(index
+            .ray_cast([2.5, 30.0, 2.5], [0.0, -1.0, 0.0], 100.0)
+            .is_none());
+    }
+    #[test]
+    fn nearest_cuboid() {
+        let index = index();
+
+        let (hit, distance) = index.nearest([100.0, 0.5, 1.0]).unwrap();
+        assert_eq!(hit, &cuboid((27, 0, 0), (28, 0, 1)));
+        assert!((distance - 71.0).abs() < f32::EPSILON);
+
+        let (_, distance) = index.nearest([4.0, 0.5, 4.0]).unwrap();
+        assert!(distance.abs() < f32::EPSILON);
+        assert!(CuboidIndex::default().nearest([0.0, 0.0, 0.0]).is_none());
+    }
+}
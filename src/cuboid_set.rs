@@ -0,0 +1,211 @@
+use crate::block_sequence::BlockSequence;
+use crate::BlockCoordinates;
+
+/// Merged cuboids which can be patched one block at a time, so a single placed
+/// or broken block does not require a full `export_cuboids`.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct CuboidSet {
+    cuboids: Vec<BlockSequence>,
+}
+
+/// Cuboids which have to be dropped from and added to a consumer (e.g. a physics
+/// world) to reflect a single block change.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct CuboidDiff {
+    pub removed: Vec<BlockSequence>,
+    pub added: Vec<BlockSequence>,
+}
+
+impl CuboidDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+impl CuboidSet {
+    #[must_use]
+    pub fn new(cuboids: Vec<BlockSequence>) -> CuboidSet {
+        CuboidSet { cuboids }
+    }
+
+    #[must_use]
+    pub fn cuboids(&self) -> &[BlockSequence] {
+        &self.cuboids
+    }
+
+    #[must_use]
+    pub fn into_cuboids(self) -> Vec<BlockSequence> {
+        self.cuboids
+    }
+
+    /// Adds a block and grows it by every cuboid sharing a whole face with it,
+    /// so only the cuboids next to the block are touched. Returns an empty
+    /// diff if the block is already covered.
+    pub fn add_block(&mut self, block: BlockCoordinates) -> CuboidDiff {
+        if self.cuboids.iter().any(|cuboid| cuboid.contains(&block)) {
+            return CuboidDiff::default();
+        }
+
+        let mut removed = vec![];
+        let mut added = BlockSequence::new(block.clone(), block);
+        while let Some(index) = self
+            .cuboids
+            .iter()
+            .position(|cuboid| added.shares_face(cuboid))
+        {
+            let neighbour = self.cuboids.remove(index);
+            added = added.union(&neighbour);
+            removed.push(neighbour);
+        }
+        self.cuboids.push(added.clone());
+
+        CuboidDiff {
+            removed,
+            added: vec![added],
+        }
+    }
+
+    /// Removes a block by splitting the cuboid containing it into at most six
+    /// cuboids around the hole. Returns an empty diff if the block is not covered.
+    pub fn remove_block(&mut self, block: &BlockCoordinates) -> CuboidDiff {
+        let removed = self.take_cuboids(|cuboid| cuboid.contains(block));
+        let added: Vec<BlockSequence> = removed
+            .iter()
+            .flat_map(|cuboid| cuboid.subtract(&BlockSequence::new(block.clone(), block.clone())))
+            .collect();
+        self.cuboids.extend(added.iter().cloned());
+
+        CuboidDiff { removed, added }
+    }
+
+    fn take_cuboids(&mut self, predicate: impl Fn(&BlockSequence) -> bool) -> Vec<BlockSequence> {
+        let (taken, kept) = std::mem::take(&mut self.cuboids)
+            .into_iter()
+            .partition(|cuboid| predicate(cuboid));
+        self.cuboids = kept;
+
+        taken
+    }
+}
+
+impl From<Vec<BlockSequence>> for CuboidSet {
+    fn from(cuboids: Vec<BlockSequence>) -> Self {
+        CuboidSet::new(cuboids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cuboid(start: (i32, i16, i32), end: (i32, i16, i32)) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(start.0, start.1, start.2),
+            BlockCoordinates::new(end.0, end.1, end.2),
+        )
+    }
+
+    #[test]
+    fn add_block_covered() {
+        let mut set = CuboidSet::new(vec![cuboid((0, 0, 0), (2, 0, 2))]);
+        let diff = set.add_block(BlockCoordinates::new(1, 0, 1));
+
+        assert!(diff.is_empty());
+        assert_eq!(set.cuboids(), &[cuboid((0, 0, 0), (2, 0, 2))]);
+    }
+    #[test]
+    fn add_block_extends_neighbour() {
+        let mut set = CuboidSet::new(vec![
+            cuboid((0, 0, 0), (2, 0, 0)),
+            cuboid((10, 0, 0), (12, 0, 0)),
+        ]);
+        let diff = set.add_block(BlockCoordinates::new(3, 0, 0));
+
+        assert_eq!(
+            diff,
+            CuboidDiff {
+                removed: vec![cuboid((0, 0, 0), (2, 0, 0))],
+                added: vec![cuboid((0, 0, 0), (3, 0, 0))],
+            }
+        );
+        assert_eq!(
+            set.cuboids(),
+            &[cuboid((10, 0, 0), (12, 0, 0)), cuboid((0, 0, 0), (3, 0, 0))]
+        );
+    }
+    #[test]
+    fn add_block_ignores_diagonal_neighbour() {
+        let mut set = CuboidSet::new(vec![cuboid((0, 0, 0), (0, 0, 0))]);
+        let diff = set.add_block(BlockCoordinates::new(1, 1, 0));
+
+        assert_eq!(
+            diff,
+            CuboidDiff {
+                removed: vec![],
+                added: vec![cuboid((1, 1, 0), (1, 1, 0))],
+            }
+        );
+    }
+    #[test]
+    fn add_block_joins_neighbours() {
+        let mut set = CuboidSet::new(vec![
+            cuboid((0, 0, 0), (0, 0, 0)),
+            cuboid((2, 0, 0), (2, 0, 0)),
+            cuboid((0, 1, 0), (2, 1, 0)),
+        ]);
+        let diff = set.add_block(BlockCoordinates::new(1, 0, 0));
+
+        assert_eq!(diff.removed.len(), 3);
+        assert_eq!(diff.added, vec![cuboid((0, 0, 0), (2, 1, 0))]);
+        assert_eq!(set.cuboids(), &[cuboid((0, 0, 0), (2, 1, 0))]);
+    }
+    #[test]
+    fn add_block_next_to_larger_face() {
+        let mut set = CuboidSet::new(vec![cuboid((0, 0, 0), (9, 0, 9))]);
+        let diff = set.add_block(BlockCoordinates::new(10, 0, 0));
+
+        assert_eq!(
+            diff,
+            CuboidDiff {
+                removed: vec![],
+                added: vec![cuboid((10, 0, 0), (10, 0, 0))],
+            }
+        );
+    }
+    #[test]
+    fn remove_block_not_covered() {
+        let mut set = CuboidSet::new(vec![cuboid((0, 0, 0), (2, 0, 2))]);
+
+        assert!(set.remove_block(&BlockCoordinates::new(5, 0, 5)).is_empty());
+    }
+    #[test]
+    fn remove_block_from_centre() {
+        let mut set = CuboidSet::new(vec![cuboid((0, 0, 0), (2, 2, 2))]);
+        let diff = set.remove_block(&BlockCoordinates::new(1, 1, 1));
+
+        assert_eq!(diff.removed, vec![cuboid((0, 0, 0), (2, 2, 2))]);
+        assert_eq!(
+            diff.added,
+            vec![
+                cuboid((0, 0, 0), (2, 0, 2)),
+                cuboid((0, 1, 0), (2, 1, 0)),
+                cuboid((0, 1, 1), (0, 1, 1)),
+                cuboid((2, 1, 1), (2, 1, 1)),
+                cuboid((0, 1, 2), (2, 1, 2)),
+                cuboid((0, 2, 0), (2, 2, 2)),
+            ]
+        );
+        let volume: usize = set.cuboids().iter().map(|c| c.blocks().count()).sum();
+        assert_eq!(volume, 26);
+    }
+    #[test]
+    fn remove_single_block_cuboid() {
+        let mut set = CuboidSet::new(vec![cuboid((4, 4, 4), (4, 4, 4))]);
+        let diff = set.remove_block(&BlockCoordinates::new(4, 4, 4));
+
+        assert_eq!(diff.removed, vec![cuboid((4, 4, 4), (4, 4, 4))]);
+        assert!(diff.added.is_empty());
+        assert!(set.cuboids().is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::block_stack::BlockStack;
+use crate::cuboid_index::CuboidIndex;
+use crate::merge::{join_cuboids, merge_blocks};
+use crate::read::{read_level_file, read_level_typed, read_level_with, TypedBlocks};
+use crate::{BlockCoordinates, BlockSequence, CuboidDiff, ExportParams};
+use anyhow::Result;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Volumes which differ between two snapshots, merged per block name. A block
+/// which changed its type is removed with the old name and added with the new.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct TypedCuboidDiff {
+    pub removed: Vec<(BlockSequence, String)>,
+    pub added: Vec<(BlockSequence, String)>,
+}
+
+impl TypedCuboidDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+/// Blocks covered by `old` but not by `new` and the other way around, as the
+/// parts of the cuboids left over, joined where they share a whole face.
+#[must_use]
+pub fn diff_cuboids(old: &[BlockSequence], new: &[BlockSequence]) -> CuboidDiff {
+    CuboidDiff {
+        removed: subtract(old, &CuboidIndex::new(new.to_vec())),
+        added: subtract(new, &CuboidIndex::new(old.to_vec())),
+    }
+}
+
+/// Diffs the blocks of two snapshots of the same level, both read over the
+/// area of `params`. Only presence counts, see [`diff_levels_typed`] to also
+/// catch blocks which changed their type.
+///
+/// # Errors
+///
+/// Same as [`crate::export_cuboids`], for either level.
+pub fn diff_levels(
+    old_lvl_path: &str,
+    new_lvl_path: &str,
+    params: ExportParams,
+) -> Result<CuboidDiff> {
+    let read = |lvl_path: &str, params: ExportParams| -> Result<FxHashSet<BlockCoordinates>> {
+        let mut blocks = FxHashSet::default();
+        read_level_with(
+            lvl_path,
+            params,
+            read_level_file,
+            |file_blocks: Vec<BlockCoordinates>| blocks.extend(file_blocks),
+        )?;
+        Ok(blocks)
+    };
+    let old = read(old_lvl_path, params.clone())?;
+    let new = read(new_lvl_path, params)?;
+
+    let merged = |blocks: &FxHashSet<BlockCoordinates>, other: &FxHashSet<BlockCoordinates>| {
+        let blocks: Vec<BlockCoordinates> = blocks.difference(other).cloned().collect();
+        merge_blocks(BlockStack::from(blocks))
+    };
+
+    Ok(CuboidDiff {
+        removed: merged(&old, &new),
+        added: merged(&new, &old),
+    })
+}
+
+/// Same as [`diff_levels`], but keeps the block names and reports blocks
+/// which changed their type as well.
+///
+/// # Errors
+///
+/// Same as [`crate::export_cuboids`], for either level.
+pub fn diff_levels_typed(
+    old_lvl_path: &str,
+    new_lvl_path: &str,
+    params: ExportParams,
+) -> Result<TypedCuboidDiff> {
+    let old = read_level_typed(old_lvl_path, params.clone())?;
+    let new = read_level_typed(new_lvl_path, params)?;
+
+    Ok(TypedCuboidDiff {
+        removed: changed(&old, &new).merge(),
+        added: changed(&new, &old).merge(),
+    })
+}
+
+/// Blocks of `blocks` which are missing or named differently in `other`.
+fn changed(blocks: &TypedBlocks, other: &TypedBlocks) -> TypedBlocks {
+    let other: FxHashMap<&BlockCoordinates, &str> = other.iter().collect();
+    let mut changed = TypedBlocks::default();
+    for (block, name) in blocks.iter() {
+        if other.get(block) != Some(&name) {
+            changed.push(block.clone(), name);
+        }
+    }
+
+    changed
+}
+
+fn subtract(cuboids: &[BlockSequence], holes: &CuboidIndex) -> Vec<BlockSequence> {
+    let mut remaining = vec![];
+    for cuboid in cuboids {
+        let mut parts = vec![cuboid.clone()];
+        for hole in holes.intersecting(cuboid) {
+            parts = parts.iter().flat_map(|part| part.subtract(hole)).collect();
+        }
+        remaining.extend(parts);
+    }
+
+    join_cuboids(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{test_dir, write_world};
+
+    fn cuboid(start: (i32, i16, i32), end: (i32, i16, i32)) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(start.0, start.1, start.2),
+            BlockCoordinates::new(end.0, end.1, end.2),
+        )
+    }
+
+    #[test]
+    fn diff_cuboids_overlapping() {
+        let old = vec![cuboid((0, 0, 0), (3, 0, 0))];
+        let new = vec![cuboid((2, 0, 0), (5, 0, 0))];
+
+        assert_eq!(
+            diff_cuboids(&old, &new),
+            CuboidDiff {
+                removed: vec![cuboid((0, 0, 0), (1, 0, 0))],
+                added: vec![cuboid((4, 0, 0), (5, 0, 0))],
+            }
+        );
+        assert!(diff_cuboids(&old, &old).is_empty());
+    }
+    #[test]
+    fn diff_cuboids_hole() {
+        let old = vec![cuboid((0, 0, 0), (2, 2, 2))];
+        let new = vec![
+            cuboid((0, 0, 0), (2, 0, 2)),
+            cuboid((0, 2, 0), (2, 2, 2)),
+            cuboid((0, 1, 0), (0, 1, 2)),
+            cuboid((2, 1, 0), (2, 1, 2)),
+            cuboid((1, 1, 0), (1, 1, 0)),
+            cuboid((1, 1, 2), (1, 1, 2)),
+        ];
+
+        assert_eq!(
+            diff_cuboids(&old, &new),
+            CuboidDiff {
+                removed: vec![cuboid((1, 1, 1), (1, 1, 1))],
+                added: vec![],
+            }
+        );
+    }
+    #[test]
+    fn diff_levels_snapshots() {
+        let (old_dir, new_dir) = (
+            test_dir("diff_levels_snapshots_old"),
+            test_dir("diff_levels_snapshots_new"),
+        );
+        let b = BlockCoordinates::new;
+        let old: Vec<(BlockCoordinates, &str)> =
+            (0..4).map(|x| (b(x, 0, 0), "minecraft:stone")).collect();
+        let mut new: Vec<(BlockCoordinates, &str)> =
+            (1..4).map(|x| (b(x, 0, 0), "minecraft:stone")).collect();
+        new[2].1 = "minecraft:dirt";
+        new.push((b(0, 1, 0), "minecraft:dirt"));
+        write_world(&old_dir, &old).unwrap();
+        write_world(&new_dir, &new).unwrap();
+        let params = ExportParams {
+            start: b(0, 0, 0),
+            end: b(10, 10, 10),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            diff_levels(&old_dir, &new_dir, params.clone()).unwrap(),
+            CuboidDiff {
+                removed: vec![cuboid((0, 0, 0), (0, 0, 0))],
+                added: vec![cuboid((0, 1, 0), (0, 1, 0))],
+            }
+        );
+        assert_eq!(
+            diff_levels_typed(&old_dir, &new_dir, params).unwrap(),
+            TypedCuboidDiff {
+                removed: vec![
+                    (cuboid((0, 0, 0), (0, 0, 0)), "minecraft:stone".to_owned()),
+                    (cuboid((3, 0, 0), (3, 0, 0)), "minecraft:stone".to_owned()),
+                ],
+                added: vec![
+                    (cuboid((3, 0, 0), (3, 0, 0)), "minecraft:dirt".to_owned()),
+                    (cuboid((0, 1, 0), (0, 1, 0)), "minecraft:dirt".to_owned()),
+                ],
+            }
+        );
+    }
+}
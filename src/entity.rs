@@ -0,0 +1,303 @@
+use crate::block_stack::BlockStack;
+use crate::merge::merge_blocks_ordered;
+use crate::read::{read_level_file_with, read_level_with};
+use crate::region::{parse_region_filename, RegionReader};
+use crate::{BlockCoordinates, BlockSequence, ExportParams};
+use anyhow::{Context, Result};
+use fastnbt::Value;
+use serde::Deserialize;
+use std::fs::DirEntry;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const REGION_CHUNKS_SIZE: i32 = 32;
+const CHUNK_SIZE: i32 = 16;
+
+/// Block entity, e.g. a chest, sign or spawner, with its raw NBT.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockEntity {
+    /// E.g. `minecraft:chest`.
+    pub id: String,
+    pub position: BlockCoordinates,
+    /// Compound saved by the game, including `id` and the position.
+    pub nbt: Value,
+}
+
+/// Entity, e.g. an item frame, armor stand or mob, with its raw NBT.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    /// E.g. `minecraft:item_frame`.
+    pub id: String,
+    /// Exact `x`, `y` and `z` of the entity.
+    pub position: [f64; 3],
+    /// Compound saved by the game, including `id` and `Pos`.
+    pub nbt: Value,
+}
+
+/// Cuboids of an area together with the block entities and entities inside of
+/// it, see [`crate::export_with_entities`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LevelExport {
+    pub cuboids: Vec<BlockSequence>,
+    /// Sorted by the y, z and x of their position.
+    pub block_entities: Vec<BlockEntity>,
+    /// Sorted by the y, z and x of their position.
+    pub entities: Vec<Entity>,
+}
+
+#[derive(Deserialize)]
+struct ChunkBlockEntities {
+    #[serde(default)]
+    block_entities: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct ChunkEntities {
+    #[serde(rename = "Entities", default)]
+    entities: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct BlockEntityFields {
+    id: String,
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+#[derive(Deserialize)]
+struct EntityFields {
+    id: String,
+    #[serde(rename = "Pos")]
+    pos: Vec<f64>,
+}
+
+type FileContent = (Vec<BlockCoordinates>, Vec<BlockEntity>, Vec<Entity>);
+
+pub(crate) fn read_level_entities(lvl_path: &str, params: ExportParams) -> Result<LevelExport> {
+    let params_order = params.order;
+    let mut blocks = BlockStack::default();
+    let mut export = LevelExport::default();
+    read_level_with(
+        lvl_path,
+        params,
+        read_entities_file,
+        |(file_blocks, block_entities, entities)| {
+            blocks.add_all(file_blocks);
+            export.block_entities.extend(block_entities);
+            export.entities.extend(entities);
+        },
+    )?;
+
+    export.cuboids = merge_blocks_ordered(blocks, params_order);
+    // region files are read in parallel, in no particular order
+    export
+        .block_entities
+        .sort_by_key(|entity| (entity.position.y, entity.position.z, entity.position.x));
+    export.entities.sort_by(|a, b| {
+        let [ax, ay, az] = a.position;
+        let [bx, by, bz] = b.position;
+        ay.total_cmp(&by)
+            .then(az.total_cmp(&bz))
+            .then(ax.total_cmp(&bx))
+    });
+
+    Ok(export)
+}
+
+fn read_entities_file(dir_entry: &DirEntry, params: &ExportParams) -> Result<FileContent> {
+    let mut block_entities = vec![];
+    let blocks = read_level_file_with(
+        dir_entry,
+        params,
+        Some(&mut |data: &[u8]| {
+            let chunk: ChunkBlockEntities =
+                fastnbt::from_bytes(data).context("Cannot parse block entities")?;
+            for nbt in chunk.block_entities {
+                let fields: BlockEntityFields =
+                    fastnbt::from_value(&nbt).context("Cannot parse block entity")?;
+                let Ok(y) = i16::try_from(fields.y) else {
+                    continue;
+                };
+                let position = BlockCoordinates::new(fields.x, y, fields.z);
+
+                if in_area(params, &position) {
+                    block_entities.push(BlockEntity {
+                        id: fields.id,
+                        position,
+                        nbt,
+                    });
+                }
+            }
+
+            Ok(())
+        }),
+    )?;
+    let entities = read_entity_region(&entities_path(&dir_entry.path()), params)?;
+
+    Ok((blocks, block_entities, entities))
+}
+
+/// Entities are stored in region files of their own since 1.17, in the
+/// `entities` directory next to `region`.
+fn entities_path(region_path: &Path) -> PathBuf {
+    let file_name = region_path.file_name().unwrap_or_default();
+
+    region_path
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new(""))
+        .join("entities")
+        .join(file_name)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn read_entity_region(path: &Path, params: &ExportParams) -> Result<Vec<Entity>> {
+    let Some((file_x, file_z)) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_region_filename)
+    else {
+        return Ok(vec![]);
+    };
+    if !path.metadata().is_ok_and(|metadata| metadata.len() > 0) {
+        return Ok(vec![]);
+    }
+
+    let mut region = RegionReader::open(path)?;
+    let mut entities = vec![];
+
+    for chunk_z in region_chunks(file_z, params.start.z, params.end.z) {
+        for chunk_x in region_chunks(file_x, params.start.x, params.end.x) {
+            let Some(data) = region.read_chunk(chunk_x, chunk_z).with_context(|| {
+                format!(
+                    "Cannot read chunk {chunk_x} {chunk_z} of {}",
+                    path.display()
+                )
+            })?
+            else {
+                continue;
+            };
+            let chunk: ChunkEntities =
+                fastnbt::from_bytes(&data).context("Cannot parse entities")?;
+
+            for nbt in chunk.entities {
+                let fields: EntityFields =
+                    fastnbt::from_value(&nbt).context("Cannot parse entity")?;
+                let [x, y, z] = fields.pos[..] else {
+                    continue;
+                };
+                let block = BlockCoordinates::new(
+                    x.floor() as i32,
+                    y.floor().clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16,
+                    z.floor() as i32,
+                );
+
+                if in_area(params, &block) {
+                    entities.push(Entity {
+                        id: fields.id,
+                        position: [x, y, z],
+                        nbt,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(entities)
+}
+
+/// Chunks of the region file `file_c` overlapping `start_c..=end_c`, relative
+/// to the region.
+#[allow(clippy::cast_sign_loss)]
+fn region_chunks(file_c: i32, start_c: i32, end_c: i32) -> Range<usize> {
+    let first = file_c * REGION_CHUNKS_SIZE;
+    let start = (start_c.div_euclid(CHUNK_SIZE) - first).max(0);
+    let end = (end_c.div_euclid(CHUNK_SIZE) - first + 1).min(REGION_CHUNKS_SIZE);
+
+    start as usize..end.max(start) as usize
+}
+
+fn in_area(params: &ExportParams, block: &BlockCoordinates) -> bool {
+    BlockSequence::new(params.start.clone(), params.end.clone()).contains(block)
+        && params
+            .shape
+            .as_ref()
+            .is_none_or(|shape| shape.contains(block))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{set_block_entities, test_dir, write_entities, write_world};
+    use fastnbt::nbt;
+
+    #[test]
+    fn read_level_entities_in_area() {
+        let dir = format!("{}/region", test_dir("read_level_entities"));
+        std::fs::create_dir_all(&dir).unwrap();
+        let b = BlockCoordinates::new;
+        write_world(
+            &dir,
+            &[
+                (b(1, 0, 1), "minecraft:chest"),
+                (b(2, 0, 1), "minecraft:stone"),
+            ],
+        )
+        .unwrap();
+        let chest = nbt!({"id": "minecraft:chest", "x": 1, "y": 0, "z": 1, "Items": []});
+        set_block_entities(
+            &dir,
+            (0, 0),
+            vec![
+                chest.clone(),
+                nbt!({"id": "minecraft:sign", "x": 10, "y": 0, "z": 1}),
+            ],
+        )
+        .unwrap();
+        let frame = nbt!({"id": "minecraft:item_frame", "Pos": [1.5, 1.0, 1.5]});
+        write_entities(
+            &dir,
+            (0, 0),
+            vec![
+                nbt!({"id": "minecraft:pig", "Pos": [12.5, 0.0, 1.5]}),
+                frame.clone(),
+            ],
+        )
+        .unwrap();
+
+        let export = read_level_entities(
+            &dir,
+            ExportParams {
+                start: b(0, 0, 0),
+                end: b(5, 5, 5),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            export,
+            LevelExport {
+                cuboids: vec![BlockSequence::new(b(1, 0, 1), b(2, 0, 1))],
+                block_entities: vec![BlockEntity {
+                    id: "minecraft:chest".to_owned(),
+                    position: b(1, 0, 1),
+                    nbt: chest,
+                }],
+                entities: vec![Entity {
+                    id: "minecraft:item_frame".to_owned(),
+                    position: [1.5, 1.0, 1.5],
+                    nbt: frame,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn region_chunks_of_area() {
+        assert_eq!(region_chunks(0, 5, 40), 0..3);
+        assert_eq!(region_chunks(-1, -20, 3), 30..32);
+        assert!(region_chunks(1, -20, 3).is_empty());
+    }
+}
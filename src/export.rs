@@ -0,0 +1,205 @@
+//! Versioned document for storing exports, e.g. as game assets.
+
+use crate::{BlockCoordinates, BlockSequence, ExportParams, ExportShape};
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+/// Version of the [`CuboidExport`] layout written by this release.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Cuboids of an export together with the parameters they were exported with.
+///
+/// The layout of format version 1 is:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "metadata": {
+///     "crate_version": "0.2.0",
+///     "source": "world/region",
+///     "start": { "x": -10, "y": -64, "z": -10 },
+///     "end": { "x": 10, "y": 100, "z": 10 },
+///     "skip_blocks": ["minecraft:water"],
+///     "only_blocks": [],
+///     "only_biomes": [],
+///     "shape": null
+///   },
+///   "cuboids": [[-10, -64, -10, 10, -60, 10]]
+/// }
+/// ```
+///
+/// Every cuboid is a flat `[start x, start y, start z, end x, end y, end z]`
+/// array with both corners inclusive. New fields may be added to `metadata`
+/// without changing the version, any other change increments it.
+///
+/// Deserializing fails for documents of a newer [`FORMAT_VERSION`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "VersionedExport")]
+pub struct CuboidExport {
+    pub version: u32,
+    pub metadata: ExportMetadata,
+    #[serde(with = "flat_cuboids")]
+    pub cuboids: Vec<BlockSequence>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportMetadata {
+    /// Version of `mca-cuboids` which produced the export.
+    pub crate_version: String,
+    /// Region directory the blocks were read from.
+    pub source: Option<String>,
+    pub start: BlockCoordinates,
+    pub end: BlockCoordinates,
+    #[serde(default)]
+    pub skip_blocks: Vec<String>,
+    #[serde(default)]
+    pub only_blocks: Vec<String>,
+    #[serde(default)]
+    pub only_biomes: Vec<String>,
+    #[serde(default)]
+    pub shape: Option<ExportShape>,
+}
+
+#[derive(Deserialize)]
+struct VersionedExport {
+    version: u32,
+    metadata: ExportMetadata,
+    #[serde(with = "flat_cuboids")]
+    cuboids: Vec<BlockSequence>,
+}
+
+impl CuboidExport {
+    #[must_use]
+    pub fn new(metadata: ExportMetadata, cuboids: Vec<BlockSequence>) -> CuboidExport {
+        CuboidExport {
+            version: FORMAT_VERSION,
+            metadata,
+            cuboids,
+        }
+    }
+}
+
+impl ExportMetadata {
+    /// Metadata of an export of `source` with `params`, as produced by the
+    /// running version of the crate.
+    #[must_use]
+    pub fn new(source: Option<&str>, params: &ExportParams) -> ExportMetadata {
+        ExportMetadata {
+            crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+            source: source.map(ToOwned::to_owned),
+            start: params.start.clone(),
+            end: params.end.clone(),
+            skip_blocks: params.skip_blocks.clone(),
+            only_blocks: params.only_blocks.clone(),
+            only_biomes: params.only_biomes.clone(),
+            shape: params.shape.clone(),
+        }
+    }
+}
+
+impl TryFrom<VersionedExport> for CuboidExport {
+    type Error = Error;
+
+    fn try_from(export: VersionedExport) -> Result<Self, Self::Error> {
+        if export.version == 0 || export.version > FORMAT_VERSION {
+            bail!(
+                "Unsupported cuboid export version {}, expected at most {FORMAT_VERSION}",
+                export.version
+            );
+        }
+
+        Ok(CuboidExport {
+            version: export.version,
+            metadata: export.metadata,
+            cuboids: export.cuboids,
+        })
+    }
+}
+
+mod flat_cuboids {
+    use crate::{BlockCoordinates, BlockSequence};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        cuboids: &[BlockSequence],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        cuboids
+            .iter()
+            .map(|c| {
+                let (s, e) = (&c.start, &c.end);
+                [s.x, i32::from(s.y), s.z, e.x, i32::from(e.y), e.z]
+            })
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<BlockSequence>, D::Error> {
+        Vec::<[i32; 6]>::deserialize(deserializer)?
+            .into_iter()
+            .map(|[sx, sy, sz, ex, ey, ez]| {
+                let y = |y: i32| i16::try_from(y).map_err(|_| D::Error::custom("y out of range"));
+
+                Ok(BlockSequence::new(
+                    BlockCoordinates::new(sx, y(sy)?, sz),
+                    BlockCoordinates::new(ex, y(ey)?, ez),
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export() -> CuboidExport {
+        CuboidExport::new(
+            ExportMetadata::new(
+                Some("world/region"),
+                &ExportParams {
+                    start: BlockCoordinates::new(-10, -64, -10),
+                    end: BlockCoordinates::new(10, 100, 10),
+                    skip_blocks: vec!["minecraft:water".to_owned()],
+                    ..Default::default()
+                },
+            ),
+            vec![BlockSequence::new(
+                BlockCoordinates::new(-10, -64, -10),
+                BlockCoordinates::new(10, -60, 10),
+            )],
+        )
+    }
+
+    #[test]
+    fn flat_cuboid_layout() {
+        let json = serde_json::to_value(export()).unwrap();
+
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["metadata"]["source"], "world/region");
+        assert_eq!(
+            json["cuboids"],
+            serde_json::json!([[-10, -64, -10, 10, -60, 10]])
+        );
+    }
+    #[test]
+    fn round_trip() {
+        let export = export();
+        let json = serde_json::to_string(&export).unwrap();
+
+        assert_eq!(serde_json::from_str::<CuboidExport>(&json).unwrap(), export);
+    }
+    #[test]
+    fn reject_unknown_versions() {
+        let mut json = serde_json::to_value(export()).unwrap();
+        json["version"] = (FORMAT_VERSION + 1).into();
+        assert!(serde_json::from_value::<CuboidExport>(json).is_err());
+
+        let mut json = serde_json::to_value(export()).unwrap();
+        json["cuboids"] = serde_json::json!([[0, 40000, 0, 0, 0, 0]]);
+        assert!(serde_json::from_value::<CuboidExport>(json).is_err());
+    }
+}
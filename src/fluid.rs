@@ -0,0 +1,177 @@
+use crate::block_stack::BlockStack;
+use crate::chunk::BlockState;
+use crate::merge::merge_blocks_ordered;
+use crate::read::{read_level_file, read_level_with, BlockSink};
+use crate::{BlockCoordinates, BlockSequence, ExportParams};
+use anyhow::Result;
+
+/// Blocks which always contain water without a `waterlogged` property.
+const ALWAYS_WATERLOGGED: [&str; 4] = [
+    "minecraft:seagrass",
+    "minecraft:tall_seagrass",
+    "minecraft:kelp",
+    "minecraft:kelp_plant",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Fluid {
+    Water,
+    Lava,
+}
+
+/// Merged blocks of the same fluid and level kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FluidVolume {
+    pub cuboid: BlockSequence,
+    pub fluid: Fluid,
+    /// Source blocks, including waterlogged blocks, instead of flowing or
+    /// falling fluid.
+    pub source: bool,
+}
+
+/// Solid cuboids and fluid volumes of an area, see [`crate::export_fluids`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FluidExport {
+    /// Every block which is not a fluid, waterlogged blocks included.
+    pub solids: Vec<BlockSequence>,
+    /// Ordered by fluid, sources first.
+    pub fluids: Vec<FluidVolume>,
+}
+
+/// Solid blocks and the fluid blocks of every [`FLUID_KINDS`] entry.
+#[derive(Default)]
+struct FluidBlocks {
+    solids: Vec<BlockCoordinates>,
+    fluids: [Vec<BlockCoordinates>; 4],
+}
+
+const FLUID_KINDS: [(Fluid, bool); 4] = [
+    (Fluid::Water, true),
+    (Fluid::Water, false),
+    (Fluid::Lava, true),
+    (Fluid::Lava, false),
+];
+
+impl BlockSink for FluidBlocks {
+    fn reserve(&mut self, additional: usize) {
+        self.solids.reserve(additional);
+    }
+
+    fn push(&mut self, block: BlockCoordinates, state: &BlockState) {
+        let fluid = match state.name.as_str() {
+            "minecraft:water" | "minecraft:bubble_column" => Some(Fluid::Water),
+            "minecraft:lava" => Some(Fluid::Lava),
+            _ => None,
+        };
+
+        if let Some(fluid) = fluid {
+            // level 0 is a source, 1-7 flow sideways and 8+ fall down
+            let source = state
+                .properties
+                .get("level")
+                .is_none_or(|level| level == "0");
+            self.push_fluid(block, fluid, source);
+        } else {
+            let waterlogged = state
+                .properties
+                .get("waterlogged")
+                .is_some_and(|w| w == "true")
+                || ALWAYS_WATERLOGGED.contains(&state.name.as_str());
+            if waterlogged {
+                self.push_fluid(block.clone(), Fluid::Water, true);
+            }
+            self.solids.push(block);
+        }
+    }
+}
+
+impl FluidBlocks {
+    fn push_fluid(&mut self, block: BlockCoordinates, fluid: Fluid, source: bool) {
+        let kind = FLUID_KINDS
+            .iter()
+            .position(|kind| *kind == (fluid, source))
+            .unwrap_or_default();
+        self.fluids[kind].push(block);
+    }
+}
+
+pub(crate) fn read_level_fluids(lvl_path: &str, params: ExportParams) -> Result<FluidExport> {
+    let params_order = params.order;
+    let mut solids = BlockStack::default();
+    let mut fluids: [Vec<BlockCoordinates>; 4] = Default::default();
+    read_level_with(lvl_path, params, read_level_file, |blocks: FluidBlocks| {
+        solids.add_all(blocks.solids);
+        for (all, file) in fluids.iter_mut().zip(blocks.fluids) {
+            all.extend(file);
+        }
+    })?;
+
+    let mut export = FluidExport {
+        solids: merge_blocks_ordered(solids, params_order),
+        fluids: vec![],
+    };
+    for ((fluid, source), blocks) in FLUID_KINDS.into_iter().zip(fluids) {
+        let cuboids = merge_blocks_ordered(BlockStack::from(blocks), params_order);
+        export
+            .fluids
+            .extend(cuboids.into_iter().map(|cuboid| FluidVolume {
+                cuboid,
+                fluid,
+                source,
+            }));
+    }
+
+    Ok(export)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{test_dir, write_world};
+
+    #[test]
+    fn read_level_fluids_separately() {
+        let dir = test_dir("read_level_fluids");
+        let cache_dir = test_dir("read_level_fluids_cache");
+        let b = BlockCoordinates::new;
+        write_world(
+            &dir,
+            &[
+                (b(0, 0, 0), "minecraft:water"),
+                (b(1, 0, 0), "minecraft:water[level=0]"),
+                (b(2, 0, 0), "minecraft:water[level=3]"),
+                (b(3, 0, 0), "minecraft:oak_stairs[waterlogged=true]"),
+                (b(4, 0, 0), "minecraft:stone"),
+                (b(0, 1, 0), "minecraft:lava[level=0]"),
+            ],
+        )
+        .unwrap();
+        let volume = |start, end, fluid, source| FluidVolume {
+            cuboid: BlockSequence::new(start, end),
+            fluid,
+            source,
+        };
+        let expected = FluidExport {
+            solids: vec![BlockSequence::new(b(3, 0, 0), b(4, 0, 0))],
+            fluids: vec![
+                volume(b(0, 0, 0), b(1, 0, 0), Fluid::Water, true),
+                volume(b(3, 0, 0), b(3, 0, 0), Fluid::Water, true),
+                volume(b(2, 0, 0), b(2, 0, 0), Fluid::Water, false),
+                volume(b(0, 1, 0), b(0, 1, 0), Fluid::Lava, true),
+            ],
+        };
+
+        for cache_dir in [None, Some(cache_dir.clone()), Some(cache_dir)] {
+            let export = read_level_fluids(
+                &dir,
+                ExportParams {
+                    start: b(0, 0, 0),
+                    end: b(10, 10, 10),
+                    cache_dir,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(export.unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`, not a stable API.
+
+use crate::chunk::Chunk;
+use crate::region::{parse_region_filename, RegionReader, REGION_CHUNKS_SIZE};
+use std::io::Cursor;
+
+#[must_use]
+pub fn region_filename(filename: &str) -> Option<(i32, i32)> {
+    parse_region_filename(filename)
+}
+
+/// Reads every chunk of the region file and decodes the ones which can be read.
+pub fn region(data: &[u8]) {
+    let Ok(mut region) = RegionReader::new(Cursor::new(data)) else {
+        return;
+    };
+
+    for chunk_z in 0..REGION_CHUNKS_SIZE {
+        for chunk_x in 0..REGION_CHUNKS_SIZE {
+            region.timestamp(chunk_x, chunk_z);
+            if let Ok(Some(chunk)) = region.read_chunk(chunk_x, chunk_z) {
+                self::chunk(&chunk);
+            }
+        }
+    }
+}
+
+/// Decodes the chunk NBT and visits every block in it.
+pub fn chunk(data: &[u8]) {
+    let Ok(chunk) = Chunk::from_bytes(data) else {
+        return;
+    };
+
+    for (x, y, z, _) in chunk.blocks() {
+        if let Some(section) = chunk.section(y) {
+            section.block(x, y, z);
+        }
+    }
+}
@@ -1,29 +1,193 @@
 #![deny(clippy::all, clippy::pedantic, clippy::cognitive_complexity)]
 #![allow(clippy::cast_possible_wrap)]
-#![feature(test)]
-
-#[cfg(test)]
-extern crate test;
 
+#[cfg(feature = "bevy")]
+pub use crate::bevy_plugin::{
+    LevelColliders, LevelCollidersFailed, LevelCollidersLoaded, LevelCollidersPlugin,
+};
 pub use crate::block_sequence::BlockSequence;
 use crate::block_stack::BlockStack;
-use crate::merge::merge_blocks;
-use anyhow::Result;
+pub use crate::codec::{
+    decode_cuboids, encode_cuboids, encode_typed_cuboids, CuboidReader, EncodeOptions,
+};
+#[cfg(feature = "rapier")]
+pub use crate::collider::{chunk_colliders, colliders, compound_collider};
+#[cfg(feature = "parry")]
+pub use crate::collider::{chunk_compound_shapes, compound_shape, cuboid_shapes, ColliderOptions};
+pub use crate::cuboid_index::CuboidIndex;
+pub use crate::cuboid_set::{CuboidDiff, CuboidSet};
+pub use crate::diff::{diff_cuboids, diff_levels, diff_levels_typed, TypedCuboidDiff};
+pub use crate::entity::{BlockEntity, Entity, LevelExport};
+pub use crate::export::{CuboidExport, ExportMetadata, FORMAT_VERSION};
+pub use crate::fluid::{Fluid, FluidExport, FluidVolume};
+pub use crate::light::LightFilter;
+use crate::merge::{merge_blocks, merge_blocks_ordered};
+pub use crate::order::{sort_cuboids, CuboidOrder};
+pub use crate::shape::ExportShape;
+pub use crate::verify::{verify, CoverageMismatch};
+pub use crate::vox::{export_vox, write_vox};
+#[cfg(feature = "writer")]
+pub use crate::write::{overlay_world, write_cuboids, write_world, WriteMode};
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "bevy")]
+mod bevy_plugin;
+mod biome;
 mod block_plate;
 mod block_sequence;
 mod block_stack;
+mod cache;
+mod chunk;
+mod codec;
+#[cfg(feature = "parry")]
+mod collider;
+mod cuboid_index;
+mod cuboid_set;
+mod diff;
+mod entity;
+mod export;
+mod fluid;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod light;
 mod merge;
+mod order;
 mod read;
+mod region;
+mod shape;
+mod stream;
+mod verify;
+mod vox;
+#[cfg(any(test, feature = "writer"))]
+mod write;
+
+/// Lowest block y of the overworld since 1.18.
+pub const WORLD_MIN_Y: i16 = -64;
+/// Highest block y of the overworld since 1.18.
+pub const WORLD_MAX_Y: i16 = 319;
+
+/// Lowest and highest block y of a dimension, the export y range is clamped to
+/// it. Dimensions of data packs can use any height, see their `min_y` and
+/// `height`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldHeight {
+    pub min_y: i16,
+    pub max_y: i16,
+}
+
+impl WorldHeight {
+    pub const OVERWORLD: WorldHeight = WorldHeight {
+        min_y: WORLD_MIN_Y,
+        max_y: WORLD_MAX_Y,
+    };
+    pub const NETHER: WorldHeight = WorldHeight {
+        min_y: 0,
+        max_y: 255,
+    };
+    pub const END: WorldHeight = WorldHeight {
+        min_y: 0,
+        max_y: 255,
+    };
+}
+
+impl Default for WorldHeight {
+    fn default() -> Self {
+        WorldHeight::OVERWORLD
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct ExportParams {
     pub start: BlockCoordinates,
     pub end: BlockCoordinates,
     pub skip_blocks: Vec<String>,
+    /// Exports only these blocks if not empty. Applied before `skip_blocks`.
+    pub only_blocks: Vec<String>,
+    /// Exports only the blocks inside of these biomes if not empty, e.g.
+    /// `minecraft:desert`.
+    pub only_biomes: Vec<String>,
+    /// Directory where the blocks of every parsed chunk are cached. Chunks which
+    /// were not modified since the previous export are not parsed again.
+    pub cache_dir: Option<String>,
+    /// Exports only the blocks inside of this shape, skipping region files and
+    /// chunks which do not overlap it.
+    pub shape: Option<ExportShape>,
+    /// Order of the exported cuboids, see [`CuboidOrder`].
+    pub order: CuboidOrder,
+    /// Height of the exported dimension, the overworld by default.
+    pub world_height: WorldHeight,
+}
+
+impl ExportParams {
+    /// Params exporting exactly the blocks of `shape`, with `start` and `end`
+    /// set to its bounds.
+    #[must_use]
+    pub fn from_shape(shape: ExportShape) -> ExportParams {
+        let bounds = shape.bounds().unwrap_or_else(|| {
+            BlockSequence::new(BlockCoordinates::default(), BlockCoordinates::default())
+        });
+
+        ExportParams {
+            start: bounds.start,
+            end: bounds.end,
+            shape: Some(shape),
+            ..Default::default()
+        }
+    }
+
+    /// Swaps the corners so `start` holds the minimum and `end` the maximum on
+    /// every axis, and clamps the y range to `world_height`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the y range lies completely outside of the world
+    /// height or `shape` does not overlap the exported box.
+    pub fn normalized(mut self) -> Result<ExportParams> {
+        let (start, end) = (&self.start, &self.end);
+        let (min_y, max_y) = (start.y.min(end.y), start.y.max(end.y));
+        let WorldHeight {
+            min_y: world_min_y,
+            max_y: world_max_y,
+        } = self.world_height;
+
+        if max_y < world_min_y || min_y > world_max_y {
+            bail!(
+                "Export y range {min_y}..={max_y} is outside of the world height \
+                 {world_min_y}..={world_max_y}"
+            );
+        }
+
+        (self.start, self.end) = (
+            BlockCoordinates::new(
+                start.x.min(end.x),
+                min_y.max(world_min_y),
+                start.z.min(end.z),
+            ),
+            BlockCoordinates::new(
+                start.x.max(end.x),
+                max_y.min(world_max_y),
+                start.z.max(end.z),
+            ),
+        );
+
+        if let Some(shape) = &self.shape {
+            let area = BlockSequence::new(self.start.clone(), self.end.clone());
+
+            if !shape
+                .bounds()
+                .is_some_and(|bounds| bounds.intersects(&area))
+            {
+                bail!("Export shape does not overlap the export area {area:?}");
+            }
+        }
+
+        Ok(self)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Default)]
 pub struct BlockCoordinates {
     pub x: i32,
     pub y: i16,
@@ -36,14 +200,118 @@ impl BlockCoordinates {
         BlockCoordinates { x, y, z }
     }
 }
+/// Reads the blocks of the level in `params` and merges them into cuboids,
+/// sorted in `params.order`.
+///
 /// # Errors
 ///
-/// Will return `Err` if `lvl_path` does not exist or the user does not have
-/// permission to read it.
+/// Will return `Err` if `lvl_path` does not exist, the user does not have
+/// permission to read it or `params` describe an empty area
+/// (see [`ExportParams::normalized`]).
 pub fn export_cuboids(lvl_path: &str, params: ExportParams) -> Result<Vec<BlockSequence>> {
+    let params_order = params.order;
     let stack = read::read_level(lvl_path, params)?;
 
-    Ok(merge_blocks(stack))
+    Ok(merge_blocks_ordered(stack, params_order))
+}
+
+/// Merges arbitrary blocks into cuboids, the same way [`export_cuboids`] merges
+/// the blocks of a world.
+#[must_use]
+pub fn merge_cuboids(blocks: Vec<BlockCoordinates>) -> Vec<BlockSequence> {
+    merge_blocks(BlockStack::from(blocks))
+}
+
+/// Exports the cuboids region file by region file, passing every cuboid to
+/// `on_cuboid` as soon as it cannot grow anymore. Unlike [`export_cuboids`],
+/// memory stays bounded for whole worlds, but cuboids are cut at region file
+/// borders (every 512 blocks on x and z) and come in no particular order.
+///
+/// # Errors
+///
+/// Same as [`export_cuboids`].
+pub fn export_cuboids_streaming(
+    lvl_path: &str,
+    params: ExportParams,
+    on_cuboid: impl FnMut(BlockSequence),
+) -> Result<()> {
+    stream::stream_level(lvl_path, params, on_cuboid)
+}
+
+/// Same as [`export_cuboids`], also returning the block entities and entities
+/// inside of the area and `params.shape`, read in the same pass over the region
+/// files. Block filters do not apply to them. Entities are read from the
+/// `entities` directory next to `lvl_path`, chunks bypass `params.cache_dir`.
+///
+/// # Errors
+///
+/// Same as [`export_cuboids`], or if an entity region file cannot be read.
+pub fn export_with_entities(lvl_path: &str, params: ExportParams) -> Result<LevelExport> {
+    entity::read_level_entities(lvl_path, params)
+}
+
+/// Reads the area in `params` like [`export_cuboids`], but merges water and
+/// lava into volumes of their own, split into source and flowing blocks.
+/// Waterlogged blocks are both solids and water sources. Block filters apply
+/// to fluids too, e.g. skipping `minecraft:water` removes water volumes.
+///
+/// # Errors
+///
+/// Same as [`export_cuboids`].
+pub fn export_fluids(lvl_path: &str, params: ExportParams) -> Result<FluidExport> {
+    fluid::read_level_fluids(lvl_path, params)
+}
+
+/// Merges the blocks of the area in `params` whose light levels are inside of
+/// `light`, e.g. air blocks dark enough for mobs to spawn. Unlike
+/// [`export_cuboids`], air is exported too, so `params.only_blocks` usually
+/// names it. Light is read as saved by the game and is not cached. Like in the
+/// game, sections saved without a `SkyLight` array take the sky light of the
+/// bottom of the nearest section above with one, or full sky light if there is
+/// none, and sections without a `BlockLight` array have no block light.
+/// Dimensions without a sky, like the Nether, save no sky light at all, so
+/// keep `light.sky_light` at `0..=15` there.
+///
+/// # Errors
+///
+/// Same as [`export_cuboids`].
+pub fn export_light_cuboids(
+    lvl_path: &str,
+    params: ExportParams,
+    light: &LightFilter,
+) -> Result<Vec<BlockSequence>> {
+    light::read_light_cuboids(lvl_path, params, light)
+}
+
+/// Merges the 4×4×4 biome cells of the area in `params` into cuboids per
+/// biome, ordered by biome name and clipped to the area. Use
+/// `params.only_biomes` to export only some biomes; block filters and
+/// `params.shape` do not apply to biomes.
+///
+/// # Errors
+///
+/// Same as [`export_cuboids`].
+pub fn export_biome_cuboids(
+    lvl_path: &str,
+    params: ExportParams,
+) -> Result<Vec<(BlockSequence, String)>> {
+    biome::read_biome_cuboids(lvl_path, params)
+}
+
+/// Same as [`export_cuboids`], but wraps the result into a [`CuboidExport`]
+/// recording the source and the normalized `params`.
+///
+/// # Errors
+///
+/// Same as [`export_cuboids`].
+pub fn export_document(lvl_path: &str, params: ExportParams) -> Result<CuboidExport> {
+    let params = params.normalized()?;
+    let metadata = ExportMetadata::new(Some(lvl_path), &params);
+
+    Ok(CuboidExport::new(
+        metadata,
+        export_cuboids(lvl_path, params)?,
+    ))
 }
 
 #[cfg(test)]
@@ -108,6 +376,191 @@ mod tests {
         );
     }
     #[test]
+    fn export_cuboids_generated_l_shape() {
+        let lvl = write::test_dir("export_cuboids_generated_l_shape");
+        let b = BlockCoordinates::new;
+        write::write_world(
+            &lvl,
+            &[
+                (b(0, 0, 0), "minecraft:stone"),
+                (b(1, 0, 0), "minecraft:stone"),
+                (b(2, 0, 0), "minecraft:dirt"),
+                (b(3, 0, 0), "minecraft:stone"),
+                (b(0, 0, 1), "minecraft:stone"),
+                (b(0, 0, 2), "minecraft:stone"),
+            ],
+        )
+        .unwrap();
+
+        let result = export_cuboids(
+            &lvl,
+            ExportParams {
+                start: b(-10, -10, -10),
+                end: b(10, 10, 10),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                BlockSequence::new(b(0, 0, 0), b(3, 0, 0)),
+                BlockSequence::new(b(0, 0, 1), b(0, 0, 2)),
+            ]
+        );
+    }
+    #[test]
+    fn export_cuboids_generated_floor_and_pillar() {
+        let lvl = write::test_dir("export_cuboids_generated_floor_and_pillar");
+        let b = BlockCoordinates::new;
+        let mut blocks = vec![];
+        for x in -2..2 {
+            for z in -2..2 {
+                blocks.push((b(x, -64, z), "minecraft:bedrock"));
+            }
+        }
+        for y in -63..-60 {
+            blocks.push((b(-2, y, -2), "minecraft:oak_log[axis=y]"));
+        }
+        write::write_world(&lvl, &blocks).unwrap();
+
+        let result = export_cuboids(
+            &lvl,
+            ExportParams {
+                start: b(-10, -64, -10),
+                end: b(10, 0, 10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            vec![
+                BlockSequence::new(b(-2, -64, -2), b(1, -64, 1)),
+                BlockSequence::new(b(-2, -63, -2), b(-2, -61, -2)),
+            ]
+        );
+    }
+    #[test]
+    fn export_cuboids_sorted() {
+        let params = ExportParams {
+            start: BlockCoordinates::new(-20, -64, -20),
+            end: BlockCoordinates::new(20, 0, 20),
+            ..Default::default()
+        };
+        let result = export_cuboids("./assets/test_lvl", params.clone()).unwrap();
+        let mut morton = export_cuboids(
+            "./assets/test_lvl",
+            ExportParams {
+                order: CuboidOrder::Morton,
+                ..params
+            },
+        )
+        .unwrap();
+
+        assert!(result.windows(2).all(|pair| {
+            let key = |c: &BlockSequence| (c.start.y, c.start.z, c.start.x);
+            key(&pair[0]) < key(&pair[1])
+        }));
+        assert_ne!(morton, result);
+        sort_cuboids(&mut morton, CuboidOrder::StartYzx);
+        assert_eq!(morton, result);
+    }
+    #[test]
+    fn export_cuboids_swapped_corners() {
+        let result = export_cuboids(
+            "./assets/test_lvl",
+            ExportParams {
+                start: BlockCoordinates::new(2, -63, 2),
+                end: BlockCoordinates::new(1, -63, 1),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            result.unwrap(),
+            vec![BlockSequence::new(
+                BlockCoordinates::new(1, -63, 1),
+                BlockCoordinates::new(2, -63, 2)
+            )]
+        );
+    }
+    #[test]
+    fn export_cuboids_outside_world_height() {
+        let result = export_cuboids(
+            "./assets/test_lvl",
+            ExportParams {
+                start: BlockCoordinates::new(1, -100, 1),
+                end: BlockCoordinates::new(2, -65, 2),
+                ..Default::default()
+            },
+        );
+
+        assert!(result.is_err());
+    }
+    #[test]
+    fn export_document_records_params() {
+        let document = export_document(
+            "./assets/test_lvl",
+            ExportParams {
+                start: BlockCoordinates::new(2, -63, 2),
+                end: BlockCoordinates::new(1, -63, 1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(document.version, FORMAT_VERSION);
+        assert_eq!(
+            document.metadata.source.as_deref(),
+            Some("./assets/test_lvl")
+        );
+        assert_eq!(document.metadata.start, BlockCoordinates::new(1, -63, 1));
+        assert_eq!(document.cuboids.len(), 1);
+    }
+    #[test]
+    fn normalized_clamps_y() {
+        let params = ExportParams {
+            start: BlockCoordinates::new(5, 500, -1),
+            end: BlockCoordinates::new(-5, -100, 1),
+            ..Default::default()
+        }
+        .normalized()
+        .unwrap();
+
+        assert_eq!(params.start, BlockCoordinates::new(-5, WORLD_MIN_Y, -1));
+        assert_eq!(params.end, BlockCoordinates::new(5, WORLD_MAX_Y, 1));
+    }
+    #[test]
+    fn normalized_clamps_y_to_dimension() {
+        let params = ExportParams {
+            start: BlockCoordinates::new(0, -64, 0),
+            end: BlockCoordinates::new(0, 319, 0),
+            world_height: WorldHeight::NETHER,
+            ..Default::default()
+        }
+        .normalized()
+        .unwrap();
+
+        assert_eq!(params.start.y, 0);
+        assert_eq!(params.end.y, 255);
+        assert!(ExportParams {
+            start: BlockCoordinates::new(0, -64, 0),
+            end: BlockCoordinates::new(0, -1, 0),
+            world_height: WorldHeight::END,
+            ..Default::default()
+        }
+        .normalized()
+        .is_err());
+    }
+    #[test]
+    fn normalized_empty_shape() {
+        let params = ExportParams::from_shape(ExportShape::Boxes(vec![]));
+
+        assert!(params.normalized().is_err());
+    }
+    #[test]
     fn export_cuboids_tetris() {
         let result = export_cuboids(
             "./assets/test_lvl",
@@ -0,0 +1,232 @@
+use crate::block_stack::BlockStack;
+use crate::chunk::SECTION_SIZE;
+use crate::merge::merge_blocks_ordered;
+use crate::read::{read_chunk, read_level_with, BlockFilter, RegionFile};
+use crate::{BlockCoordinates, BlockSequence, ExportParams};
+use anyhow::Result;
+use std::fs::DirEntry;
+use std::ops::RangeInclusive;
+
+/// Light of sections saved without a `SkyLight` array and without one above
+/// them, which are open to the sky.
+const MISSING_SKY_LIGHT: u8 = 15;
+/// Light of sections saved without a `BlockLight` array, the game leaves it
+/// out when no light source reaches the section.
+const MISSING_BLOCK_LIGHT: u8 = 0;
+
+/// Light levels of the blocks exported by [`crate::export_light_cuboids`],
+/// both `0..=15` by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LightFilter {
+    /// Light of torches, lava and other light sources.
+    pub block_light: RangeInclusive<u8>,
+    /// Light coming from the sky.
+    pub sky_light: RangeInclusive<u8>,
+}
+
+impl Default for LightFilter {
+    fn default() -> Self {
+        LightFilter {
+            block_light: 0..=15,
+            sky_light: 0..=15,
+        }
+    }
+}
+
+pub(crate) fn read_light_cuboids(
+    lvl_path: &str,
+    params: ExportParams,
+    light: &LightFilter,
+) -> Result<Vec<BlockSequence>> {
+    let params_order = params.order;
+    let light = light.clone();
+    let mut stack = BlockStack::default();
+    read_level_with(
+        lvl_path,
+        params,
+        move |dir_entry, params| read_light_file(dir_entry, params, &light),
+        |blocks| stack.add_all(blocks),
+    )?;
+
+    Ok(merge_blocks_ordered(stack, params_order))
+}
+
+/// Blocks of a region file, air included, whose light is inside of `light`.
+/// Light is not cached, chunks are always parsed from the region file.
+#[allow(clippy::cast_possible_truncation)]
+fn read_light_file(
+    dir_entry: &DirEntry,
+    params: &ExportParams,
+    light: &LightFilter,
+) -> Result<Vec<BlockCoordinates>> {
+    let mut file = RegionFile::open(dir_entry, params)?;
+    let filter = BlockFilter::new(&file, params);
+    let mut blocks = vec![];
+
+    file.for_each_chunk(
+        params,
+        |region, _cache, (chunk_x, chunk_z), (chunk_min_x, chunk_min_z)| {
+            let Some(chunk) = read_chunk(region, chunk_x, chunk_z)? else {
+                return Ok(());
+            };
+
+            for y in params.start.y..=params.end.y {
+                let Some(section) = chunk.section(y) else {
+                    continue;
+                };
+                for x in 0..SECTION_SIZE {
+                    for z in 0..SECTION_SIZE {
+                        let Some(block) = section.block(x, y, z) else {
+                            continue;
+                        };
+                        let (block_light, sky_light) = section.light(x, y, z);
+                        let block_light = block_light.unwrap_or(MISSING_BLOCK_LIGHT);
+                        let sky_light = sky_light
+                            .or_else(|| chunk.sky_light_above(x, y, z))
+                            .unwrap_or(MISSING_SKY_LIGHT);
+                        if !light.block_light.contains(&block_light)
+                            || !light.sky_light.contains(&sky_light)
+                        {
+                            continue;
+                        }
+
+                        let point = BlockCoordinates::new(
+                            chunk_min_x + x as i32,
+                            y,
+                            chunk_min_z + z as i32,
+                        );
+                        let biome = if filter.with_biomes() {
+                            section.biome(x, y, z)
+                        } else {
+                            None
+                        };
+                        if filter.matches(&point, &block.name, biome) {
+                            blocks.push(point);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{set_section_light, test_dir, write_world};
+
+    #[test]
+    fn read_light_cuboids_by_level() {
+        let dir = test_dir("read_light_cuboids");
+        let b = BlockCoordinates::new;
+        write_world(&dir, &[(b(0, 0, 0), "minecraft:stone")]).unwrap();
+        // a wall of torch light along x 0-3
+        set_section_light(
+            &dir,
+            (0, 0, 0),
+            |index| if index % 16 < 4 { 14 } else { 3 },
+            15,
+        )
+        .unwrap();
+        let params = ExportParams {
+            start: b(0, 0, 0),
+            end: b(7, 0, 0),
+            only_blocks: vec!["minecraft:air".to_owned()],
+            ..Default::default()
+        };
+        let read = |block_light, sky_light| {
+            read_light_cuboids(
+                &dir,
+                params.clone(),
+                &LightFilter {
+                    block_light,
+                    sky_light,
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            read(0..=7, 0..=15),
+            vec![BlockSequence::new(b(4, 0, 0), b(7, 0, 0))]
+        );
+        assert_eq!(
+            read(8..=15, 15..=15),
+            vec![BlockSequence::new(b(1, 0, 0), b(3, 0, 0))]
+        );
+        assert_eq!(read(0..=15, 0..=14), vec![]);
+    }
+    #[test]
+    fn read_light_cuboids_without_light_arrays() {
+        let dir = test_dir("read_light_cuboids_without_light_arrays");
+        let b = BlockCoordinates::new;
+        // written without any BlockLight or SkyLight
+        write_world(&dir, &[(b(0, 0, 0), "minecraft:stone")]).unwrap();
+        let params = ExportParams {
+            start: b(1, 0, 0),
+            end: b(3, 0, 0),
+            only_blocks: vec!["minecraft:air".to_owned()],
+            ..Default::default()
+        };
+        let read = |block_light, sky_light| {
+            read_light_cuboids(
+                &dir,
+                params.clone(),
+                &LightFilter {
+                    block_light,
+                    sky_light,
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            read(0..=0, 15..=15),
+            vec![BlockSequence::new(b(1, 0, 0), b(3, 0, 0))]
+        );
+        assert_eq!(read(0..=15, 0..=7), vec![]);
+        assert_eq!(read(1..=15, 0..=15), vec![]);
+    }
+    #[test]
+    fn read_light_cuboids_sky_light_from_above() {
+        let dir = test_dir("read_light_cuboids_sky_light_from_above");
+        let b = BlockCoordinates::new;
+        write_world(
+            &dir,
+            &[
+                (b(0, 0, 0), "minecraft:stone"),
+                (b(0, 16, 0), "minecraft:stone"),
+            ],
+        )
+        .unwrap();
+        // a dark section above the one saved without light arrays
+        set_section_light(&dir, (0, 1, 0), |_| 0, 0).unwrap();
+        let params = ExportParams {
+            start: b(1, 0, 0),
+            end: b(3, 0, 0),
+            only_blocks: vec!["minecraft:air".to_owned()],
+            ..Default::default()
+        };
+        let read = |sky_light| {
+            read_light_cuboids(
+                &dir,
+                params.clone(),
+                &LightFilter {
+                    sky_light,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            read(0..=0),
+            vec![BlockSequence::new(b(1, 0, 0), b(3, 0, 0))]
+        );
+        assert_eq!(read(1..=15), vec![]);
+    }
+}
@@ -1,30 +1,215 @@
-use crate::block_sequence::BlockSequence;
+use crate::block_plate::BlockPlate;
+use crate::block_sequence::{BlockSequence, Face};
 use crate::block_stack::BlockStack;
+use crate::order::{sort_cuboids, CuboidOrder};
 use crate::BlockCoordinates;
 use rustc_hash::{FxHashMap, FxHasher};
 use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
+use std::num::NonZeroUsize;
+use std::{panic, thread};
+
+/// Blocks below which a plate merging thread costs more than it saves.
+const MIN_BLOCKS_PER_WORKER: usize = 16 * 1024;
 
 pub(crate) fn merge_blocks(block_stack: BlockStack) -> Vec<BlockSequence> {
-    let mut all_sequences_by_end_y = FxHashMap::default();
+    let mut merger = Merger::default();
+    let mut all_sequences = vec![];
+
+    for (y, plane_sequences) in merge_plates(block_stack.plates()) {
+        all_sequences.extend(merger.add_plane(y, plane_sequences));
+    }
+    all_sequences.extend(merger.finish());
+    sort_cuboids(&mut all_sequences, CuboidOrder::StartYzx);
 
-    for (y, plate) in block_stack.plates() {
-        let mut plane_sequences = vec![];
+    all_sequences
+}
 
-        for (z, row) in plate.rows() {
-            let row_sequences = merge_blocks_x_row(row);
+/// Same as [`merge_blocks`], with the cuboids sorted in `order`. Every export
+/// merges through here, so this is where the `debug-verify` feature checks the
+/// cuboids against the blocks.
+pub(crate) fn merge_blocks_ordered(
+    block_stack: BlockStack,
+    order: CuboidOrder,
+) -> Vec<BlockSequence> {
+    #[cfg(all(feature = "debug-verify", debug_assertions))]
+    let blocks = block_stack.blocks();
+    let mut cuboids = merge_blocks(block_stack);
+    #[cfg(all(feature = "debug-verify", debug_assertions))]
+    debug_assert_eq!(crate::verify(&cuboids, &blocks), Ok(()));
+    if order != CuboidOrder::StartYzx {
+        sort_cuboids(&mut cuboids, order);
+    }
+
+    cuboids
+}
+
+/// Joins cuboids sharing a whole face until no two of them do, without
+/// breaking them into blocks. Unlike [`merge_blocks`] the result depends on the
+/// shape of the input and is not the same as merging their blocks.
+pub(crate) fn join_cuboids(cuboids: Vec<BlockSequence>) -> Vec<BlockSequence> {
+    let mut cuboids: Vec<Option<BlockSequence>> = cuboids.into_iter().map(Some).collect();
+    let mut lower_faces = FxHashMap::default();
+    let mut upper_faces = FxHashMap::default();
+    for (index, cuboid) in cuboids.iter().enumerate() {
+        add_faces(cuboid.as_ref(), index, &mut lower_faces, &mut upper_faces);
+    }
 
-            stretch_sequences_by_z(row_sequences, &mut plane_sequences, z);
+    for index in 0..cuboids.len() {
+        // a grown cuboid is checked again, earlier cuboids sharing a face with
+        // it are found through the face maps
+        while let Some(cuboid) = &cuboids[index] {
+            let neighbour = cuboid.faces().iter().find_map(|(lower, upper)| {
+                let found = upper_faces.get(lower).or_else(|| lower_faces.get(upper));
+                found.copied().filter(|other| *other != index)
+            });
+            let Some(other) = neighbour else {
+                break;
+            };
+
+            let (cuboid, other) = (cuboids[index].take(), cuboids[other].take());
+            remove_faces(cuboid.as_ref(), &mut lower_faces, &mut upper_faces);
+            remove_faces(other.as_ref(), &mut lower_faces, &mut upper_faces);
+            cuboids[index] = cuboid
+                .zip(other)
+                .map(|(cuboid, other)| cuboid.union(&other));
+            add_faces(
+                cuboids[index].as_ref(),
+                index,
+                &mut lower_faces,
+                &mut upper_faces,
+            );
         }
+    }
+    let mut cuboids: Vec<BlockSequence> = cuboids.into_iter().flatten().collect();
+    sort_cuboids(&mut cuboids, CuboidOrder::StartYzx);
 
-        stretch_sequences_by_y(&mut all_sequences_by_end_y, plane_sequences, y);
+    cuboids
+}
+
+fn add_faces(
+    cuboid: Option<&BlockSequence>,
+    index: usize,
+    lower_faces: &mut FxHashMap<Face, usize>,
+    upper_faces: &mut FxHashMap<Face, usize>,
+) {
+    for (lower, upper) in cuboid.iter().flat_map(|cuboid| cuboid.faces()) {
+        lower_faces.insert(lower, index);
+        upper_faces.insert(upper, index);
     }
-    let mut all_sequences = vec![];
-    for (.., seq) in all_sequences_by_end_y {
-        all_sequences.extend(seq);
+}
+
+fn remove_faces(
+    cuboid: Option<&BlockSequence>,
+    lower_faces: &mut FxHashMap<Face, usize>,
+    upper_faces: &mut FxHashMap<Face, usize>,
+) {
+    for (lower, upper) in cuboid.iter().flat_map(|cuboid| cuboid.faces()) {
+        lower_faces.remove(&lower);
+        upper_faces.remove(&upper);
     }
+}
 
-    all_sequences
+/// Merges every plate on its own, in parallel for big stacks. Only the
+/// stitching along y depends on the neighbour plates.
+fn merge_plates(plates: Vec<(isize, BlockPlate)>) -> Vec<(isize, Vec<BlockSequence>)> {
+    let blocks: usize = plates.iter().map(|(_, plate)| plate.len()).sum();
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(blocks / MIN_BLOCKS_PER_WORKER);
+
+    merge_plates_on(plates, workers)
+}
+
+fn merge_plates_on(
+    plates: Vec<(isize, BlockPlate)>,
+    workers: usize,
+) -> Vec<(isize, Vec<BlockSequence>)> {
+    let workers = workers.min(plates.len()).max(1);
+    if workers == 1 {
+        return plates
+            .into_iter()
+            .map(|(y, plate)| (y, merge_plate(plate)))
+            .collect();
+    }
+
+    // plates are spread round-robin, as the lower ones are usually denser
+    let mut batches: Vec<Vec<(isize, BlockPlate)>> = (0..workers).map(|_| vec![]).collect();
+    for (index, plate) in plates.into_iter().enumerate() {
+        batches[index % workers].push(plate);
+    }
+
+    let mut merged: Vec<(isize, Vec<BlockSequence>)> = thread::scope(|scope| {
+        let handles: Vec<_> = batches
+            .into_iter()
+            .map(|batch| {
+                scope.spawn(|| {
+                    batch
+                        .into_iter()
+                        .map(|(y, plate)| (y, merge_plate(plate)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    });
+    merged.sort_by_key(|(y, _)| *y);
+
+    merged
+}
+
+fn merge_plate(plate: BlockPlate) -> Vec<BlockSequence> {
+    let mut plane_sequences = vec![];
+
+    for (z, row) in plate.rows() {
+        let row_sequences = merge_blocks_x_row(row);
+
+        stretch_sequences_by_z(row_sequences, &mut plane_sequences, z);
+    }
+
+    plane_sequences
+}
+
+/// Incremental form of [`merge_blocks`], fed plate by plate in ascending y.
+#[derive(Default)]
+pub(crate) struct Merger {
+    all_sequences_by_end_y: FxHashMap<isize, Vec<BlockSequence>>,
+}
+
+impl Merger {
+    /// Merges the plate at `y` into the cuboids below it and returns the
+    /// cuboids which cannot grow anymore, because nothing of them reaches `y`.
+    pub(crate) fn add_plate(&mut self, y: isize, plate: BlockPlate) -> Vec<BlockSequence> {
+        self.add_plane(y, merge_plate(plate))
+    }
+
+    fn add_plane(&mut self, y: isize, plane_sequences: Vec<BlockSequence>) -> Vec<BlockSequence> {
+        stretch_sequences_by_y(&mut self.all_sequences_by_end_y, plane_sequences, y);
+
+        let finished: Vec<isize> = self
+            .all_sequences_by_end_y
+            .keys()
+            .copied()
+            .filter(|end_y| *end_y < y)
+            .collect();
+        finished
+            .into_iter()
+            .filter_map(|end_y| self.all_sequences_by_end_y.remove(&end_y))
+            .flatten()
+            .collect()
+    }
+
+    /// Returns all cuboids which were not finished yet.
+    pub(crate) fn finish(self) -> Vec<BlockSequence> {
+        self.all_sequences_by_end_y
+            .into_values()
+            .flatten()
+            .collect()
+    }
 }
 
 fn stretch_sequences_by_y(
@@ -111,7 +296,79 @@ fn merge_blocks_x_row(mut row: Vec<BlockCoordinates>) -> Vec<BlockSequence> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn join_cuboids_shared_faces() {
+        let b = BlockCoordinates::new;
+        let cuboids = vec![
+            BlockSequence::new(b(0, 0, 0), b(1, 0, 0)),
+            BlockSequence::new(b(0, 1, 0), b(1, 1, 0)),
+            BlockSequence::new(b(2, 0, 0), b(2, 1, 0)),
+            BlockSequence::new(b(5, 0, 0), b(5, 0, 0)),
+        ];
+
+        assert_eq!(
+            join_cuboids(cuboids),
+            vec![
+                BlockSequence::new(b(0, 0, 0), b(2, 1, 0)),
+                BlockSequence::new(b(5, 0, 0), b(5, 0, 0)),
+            ]
+        );
+    }
+    #[test]
+    fn join_cuboids_grown_neighbours() {
+        let b = BlockCoordinates::new;
+        // the first cuboid only fits the grown last one
+        let cuboids = vec![
+            BlockSequence::new(b(0, 1, 0), b(1, 1, 0)),
+            BlockSequence::new(b(3, 0, 0), b(3, 0, 0)),
+            BlockSequence::new(b(0, 0, 0), b(0, 0, 0)),
+            BlockSequence::new(b(1, 0, 0), b(1, 0, 0)),
+        ];
+
+        assert_eq!(
+            join_cuboids(cuboids),
+            vec![
+                BlockSequence::new(b(0, 0, 0), b(1, 1, 0)),
+                BlockSequence::new(b(3, 0, 0), b(3, 0, 0)),
+            ]
+        );
 
+        let row = (0..100_000)
+            .rev()
+            .map(|x| BlockSequence::new(b(x, 0, 0), b(x, 0, 0)));
+        assert_eq!(
+            join_cuboids(row.collect()),
+            vec![BlockSequence::new(b(0, 0, 0), b(99_999, 0, 0))]
+        );
+    }
+    #[test]
+    fn merger_emits_finished_cuboids() {
+        let plate = |y: i16| {
+            let mut plate = BlockPlate::default();
+            plate.add_block(BlockCoordinates::new(0, y, 0));
+            plate
+        };
+        let mut merger = Merger::default();
+
+        assert_eq!(merger.add_plate(0, plate(0)), vec![]);
+        assert_eq!(merger.add_plate(1, plate(1)), vec![]);
+        assert_eq!(
+            merger.add_plate(3, plate(3)),
+            vec![BlockSequence::new(
+                BlockCoordinates::new(0, 0, 0),
+                BlockCoordinates::new(0, 1, 0)
+            )]
+        );
+        assert_eq!(
+            merger.finish(),
+            vec![BlockSequence::new(
+                BlockCoordinates::new(0, 3, 0),
+                BlockCoordinates::new(0, 3, 0)
+            )]
+        );
+    }
     #[test]
     fn merge_blocks_x_row_simple() {
         let b = |x| -> BlockCoordinates { BlockCoordinates::new(x, 0, 0) };
@@ -146,4 +403,21 @@ mod tests {
             ]
         );
     }
+
+    proptest! {
+        #[test]
+        fn parallel_merge_matches_sequential(
+            blocks in prop::collection::hash_set((0..30i32, 0..20i16, 0..30i32), 0..2000),
+        ) {
+            let mut stack = BlockStack::default();
+            for (x, y, z) in blocks {
+                stack.add_block(BlockCoordinates::new(x, y, z));
+            }
+
+            prop_assert_eq!(
+                merge_plates_on(stack.clone().plates(), 4),
+                merge_plates_on(stack.plates(), 1)
+            );
+        }
+    }
 }
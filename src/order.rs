@@ -0,0 +1,94 @@
+use crate::BlockSequence;
+
+/// Order of the cuboids returned by an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CuboidOrder {
+    /// By the `y`, then `z`, then `x` of the start corner.
+    #[default]
+    StartYzx,
+    /// Along the Morton (Z-order) curve through the start corners, which keeps
+    /// cuboids close in the world close in the output.
+    Morton,
+}
+
+/// Sorts `cuboids` in `order`. Cuboids of an export never share a start
+/// corner, so the result does not depend on the initial order.
+pub fn sort_cuboids(cuboids: &mut [BlockSequence], order: CuboidOrder) {
+    match order {
+        CuboidOrder::StartYzx => {
+            cuboids.sort_unstable_by_key(|c| (c.start.y, c.start.z, c.start.x));
+        }
+        CuboidOrder::Morton => cuboids.sort_by_cached_key(morton_key),
+    }
+}
+
+fn morton_key(cuboid: &BlockSequence) -> u128 {
+    // flipping the sign bit keeps the order of negative coordinates
+    let x = cuboid.start.x.cast_unsigned() ^ 0x8000_0000;
+    let y = i32::from(cuboid.start.y).cast_unsigned() ^ 0x8000_0000;
+    let z = cuboid.start.z.cast_unsigned() ^ 0x8000_0000;
+
+    (0..32).fold(0, |key, bit| {
+        key | (u128::from((x >> bit) & 1) << (bit * 3))
+            | (u128::from((y >> bit) & 1) << (bit * 3 + 1))
+            | (u128::from((z >> bit) & 1) << (bit * 3 + 2))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockCoordinates;
+
+    fn cuboid(x: i32, y: i16, z: i32) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(x, y, z),
+            BlockCoordinates::new(x, y, z),
+        )
+    }
+
+    #[test]
+    fn sort_by_start_yzx() {
+        let mut cuboids = vec![
+            cuboid(1, 0, 0),
+            cuboid(0, 1, 0),
+            cuboid(0, 0, 1),
+            cuboid(-1, 0, 0),
+        ];
+        sort_cuboids(&mut cuboids, CuboidOrder::StartYzx);
+
+        assert_eq!(
+            cuboids,
+            vec![
+                cuboid(-1, 0, 0),
+                cuboid(1, 0, 0),
+                cuboid(0, 0, 1),
+                cuboid(0, 1, 0)
+            ]
+        );
+    }
+    #[test]
+    fn sort_by_morton() {
+        let mut cuboids = vec![
+            cuboid(1, 1, 1),
+            cuboid(2, 0, 0),
+            cuboid(0, 0, 0),
+            cuboid(1, 0, 0),
+            cuboid(-1, 0, 0),
+            cuboid(0, 1, 0),
+        ];
+        sort_cuboids(&mut cuboids, CuboidOrder::Morton);
+
+        assert_eq!(
+            cuboids,
+            vec![
+                cuboid(-1, 0, 0),
+                cuboid(0, 0, 0),
+                cuboid(1, 0, 0),
+                cuboid(0, 1, 0),
+                cuboid(1, 1, 1),
+                cuboid(2, 0, 0),
+            ]
+        );
+    }
+}
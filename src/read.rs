@@ -1,168 +1,627 @@
 use anyhow::{Context, Result};
+use rustc_hash::FxHashMap;
 use std::fs::{DirEntry, File};
 use std::ops::RangeInclusive;
 use std::os::unix::prelude::MetadataExt;
+use std::path::Path;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::{cmp, fs, thread};
 
-use crate::{BlockCoordinates, BlockStack, ExportParams};
-use fastanvil::{Chunk, CurrentJavaChunk, Region};
-use fastnbt::from_bytes;
+use crate::cache::{CachedChunk, ChunkCache};
+use crate::chunk::{BlockState, Chunk, BIOME_CELL_SIZE, SECTION_SIZE};
+use crate::merge::merge_blocks_ordered;
+use crate::region::{parse_region_filename, RegionReader};
+use crate::{BlockCoordinates, BlockSequence, BlockStack, CuboidOrder, ExportParams, ExportShape};
 
-const CHUNK_BLOCKS_SIZE: usize = 16;
+const CHUNK_BLOCKS_SIZE: usize = SECTION_SIZE;
 const FILE_CHUNKS_SIZE: i32 = 32;
+#[allow(clippy::cast_possible_truncation)]
 const FILE_BLOCKS_SIZE: i32 = CHUNK_BLOCKS_SIZE as i32 * FILE_CHUNKS_SIZE;
 
-pub(crate) fn read_level(lvl_path: &str, params: ExportParams) -> Result<BlockStack> {
-    let needed_filenames = get_needed_filenames(&params);
+/// Receives the uncompressed NBT of a chunk, see [`read_level_file_with`].
+pub(crate) type ChunkHook<'a> = &'a mut dyn FnMut(&[u8]) -> Result<()>;
 
-    let paths = fs::read_dir(lvl_path).context("Cannot read lvl dir")?;
-    let files: Vec<DirEntry> = paths
-        .into_iter()
-        .flatten()
-        .filter(|dir| {
-            dir.file_name().to_str().map_or(false, |filename| {
-                needed_filenames.contains(&filename.to_owned())
+/// Receives the blocks read from a region file.
+pub(crate) trait BlockSink: Default + Send + 'static {
+    fn reserve(&mut self, additional: usize);
+    fn push(&mut self, block: BlockCoordinates, state: &BlockState);
+}
+
+impl BlockSink for Vec<BlockCoordinates> {
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional);
+    }
+
+    fn push(&mut self, block: BlockCoordinates, _state: &BlockState) {
+        Vec::push(self, block);
+    }
+}
+
+/// Blocks together with the index of their name.
+#[derive(Default)]
+pub(crate) struct TypedBlocks {
+    names: Vec<String>,
+    indexes: FxHashMap<String, usize>,
+    blocks: Vec<(BlockCoordinates, usize)>,
+}
+
+impl TypedBlocks {
+    fn index_of(&mut self, name: &str) -> usize {
+        if let Some(index) = self.indexes.get(name) {
+            return *index;
+        }
+
+        let index = self.names.len();
+        self.names.push(name.to_owned());
+        self.indexes.insert(name.to_owned(), index);
+        index
+    }
+
+    pub(crate) fn push(&mut self, block: BlockCoordinates, name: &str) {
+        let index = self.index_of(name);
+        self.blocks.push((block, index));
+    }
+
+    pub(crate) fn extend(&mut self, other: TypedBlocks) {
+        let indexes: Vec<usize> = other.names.iter().map(|name| self.index_of(name)).collect();
+        self.blocks.extend(
+            other
+                .blocks
+                .into_iter()
+                .map(|(block, index)| (block, indexes[index])),
+        );
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&BlockCoordinates, &str)> {
+        self.blocks
+            .iter()
+            .map(|(block, index)| (block, self.names[*index].as_str()))
+    }
+
+    /// Merges the blocks of every name on their own, ordered by name.
+    pub(crate) fn merge(self) -> Vec<(BlockSequence, String)> {
+        let mut groups: Vec<Vec<BlockCoordinates>> = self.names.iter().map(|_| vec![]).collect();
+        for (block, index) in self.blocks {
+            groups[index].push(block);
+        }
+
+        let mut named: Vec<(String, Vec<BlockCoordinates>)> =
+            self.names.into_iter().zip(groups).collect();
+        named.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        named
+            .into_iter()
+            .filter(|(_, blocks)| !blocks.is_empty())
+            .flat_map(|(name, blocks)| {
+                merge_blocks_ordered(BlockStack::from(blocks), CuboidOrder::StartYzx)
+                    .into_iter()
+                    .map(move |cuboid| (cuboid, name.clone()))
             })
-        })
-        .filter(|dir| dir.metadata().map_or(false, |meta| meta.size() > 0))
-        .collect();
+            .collect()
+    }
+}
+
+impl BlockSink for TypedBlocks {
+    fn reserve(&mut self, additional: usize) {
+        self.blocks.reserve(additional);
+    }
+
+    fn push(&mut self, block: BlockCoordinates, state: &BlockState) {
+        TypedBlocks::push(self, block, &state.name);
+    }
+}
+
+pub(crate) fn read_level(lvl_path: &str, params: ExportParams) -> Result<BlockStack> {
+    let mut stack = BlockStack::default();
+    read_level_with(
+        lvl_path,
+        params,
+        read_level_file,
+        |blocks: Vec<BlockCoordinates>| {
+            stack.add_all(blocks);
+        },
+    )?;
+
+    Ok(stack)
+}
+
+pub(crate) fn read_level_typed(lvl_path: &str, params: ExportParams) -> Result<TypedBlocks> {
+    let mut typed = TypedBlocks::default();
+    read_level_with(lvl_path, params, read_level_file, |blocks| {
+        typed.extend(blocks);
+    })?;
+
+    Ok(typed)
+}
+
+/// Reads every needed region file with `read_file` on its own thread and
+/// passes the result of each file to `combine`.
+pub(crate) fn read_level_with<S: Send + 'static>(
+    lvl_path: &str,
+    params: ExportParams,
+    read_file: impl Fn(&DirEntry, &ExportParams) -> Result<S> + Send + Sync + 'static,
+    mut combine: impl FnMut(S),
+) -> Result<()> {
+    let params = params.normalized()?;
+    let files = get_region_files(lvl_path, &params)?;
 
     let (sender, receiver) = channel();
     let export_params = Arc::new(params);
+    let read_file = Arc::new(read_file);
 
     for dir_entry in files {
         let p = export_params.clone();
+        let read_file = read_file.clone();
         let own_sender = sender.clone();
 
         thread::spawn(move || {
-            let blocks = read_level_file(&dir_entry, &p).unwrap();
-
-            own_sender
-                .send(blocks)
-                .context("Cannot send parsed coordinates from thread")
-                .unwrap();
+            // the receiver is only dropped after every sender, sending cannot fail
+            let _ = own_sender.send(read_file(&dir_entry, &p));
         });
     }
     drop(sender);
 
-    let mut stack = BlockStack::default();
     for blocks in receiver {
-        stack.add_all(blocks);
+        combine(blocks?);
     }
 
-    Ok(stack)
+    Ok(())
 }
 
-fn read_level_file(dir_entry: &DirEntry, params: &ExportParams) -> Result<Vec<BlockCoordinates>> {
-    // let mut blocks = vec![];
-    let blocks_to_skip: Vec<&str> = params
-        .skip_blocks
-        .iter()
-        .map(std::ops::Deref::deref)
-        .collect();
-
-    let (filepath, filename) = (
-        dir_entry
-            .path()
-            .to_str()
-            .context("Cannot convert file path to str")?
-            .to_string(),
-        dir_entry
-            .file_name()
-            .to_str()
-            .context("Cannot convert file name to str")?
-            .to_string(),
-    );
-
-    let file = File::open(&filepath).context(format!("Cannot open file {}", &filepath))?;
-    let d = filename[2..filename.len() - 4]
-        .split('.')
-        .collect::<Vec<&str>>();
-    let file_x = d[0]
-        .parse::<i32>()
-        .context(format!("File {} has wrong name", &filepath))?;
-    let file_z = d[1]
-        .parse::<i32>()
-        .context(format!("File {} has wrong name", &filepath))?;
-    let (x_range, z_range) = get_chunk_xz_ranges(file_x, file_z, params);
-
-    let mut region = Region::from_stream(file).context("Cannot create region from file.")?;
-    let file_min_x = file_x * FILE_CHUNKS_SIZE * CHUNK_BLOCKS_SIZE as i32;
-    let file_min_z = file_z * FILE_CHUNKS_SIZE * CHUNK_BLOCKS_SIZE as i32;
-    let y_range_len = range_len_y(&(params.start.y..=params.end.y));
-    let mut blocks =
-        Vec::with_capacity(range_len(&x_range) * range_len(&z_range) * (y_range_len / 2));
-
-    for raw_chunk in region.iter().flatten() {
-        let mut chunk_min_x = (raw_chunk.x * CHUNK_BLOCKS_SIZE) as i32;
-        if file_x < 0 {
-            chunk_min_x = -chunk_min_x + FILE_BLOCKS_SIZE;
+/// Non-empty region files of `lvl_path` overlapping the normalized `params`.
+pub(crate) fn get_region_files(lvl_path: &str, params: &ExportParams) -> Result<Vec<DirEntry>> {
+    let needed_filenames = get_needed_filenames(params);
+    let paths = fs::read_dir(lvl_path).context("Cannot read lvl dir")?;
+
+    Ok(paths
+        .into_iter()
+        .flatten()
+        .filter(|dir| {
+            dir.file_name()
+                .to_str()
+                .is_some_and(|filename| needed_filenames.contains(&filename.to_owned()))
+        })
+        .filter(|dir| dir.metadata().is_ok_and(|meta| meta.size() > 0))
+        .collect())
+}
+
+pub(crate) fn read_level_file<S: BlockSink>(
+    dir_entry: &DirEntry,
+    params: &ExportParams,
+) -> Result<S> {
+    read_level_file_with(dir_entry, params, None)
+}
+
+/// Same as [`read_level_file`], also passing the uncompressed NBT of every
+/// generated chunk overlapping the area to `on_chunk`. Such chunks are always
+/// parsed from the region file, the cache is not used.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn read_level_file_with<S: BlockSink>(
+    dir_entry: &DirEntry,
+    params: &ExportParams,
+    mut on_chunk: Option<ChunkHook<'_>>,
+) -> Result<S> {
+    let mut file = RegionFile::open(dir_entry, params)?;
+    let filter = BlockFilter::new(&file, params);
+    let (x_range, z_range) = (&file.x_range, &file.z_range);
+    let y_range = params.start.y..=params.end.y;
+    let mut blocks = S::default();
+    blocks.reserve(range_len(x_range) * range_len(z_range) * (range_len_y(&y_range) / 2));
+
+    file.for_each_chunk(
+        params,
+        |region, cache, raw_chunk, (chunk_min_x, chunk_min_z)| {
+            let push_block =
+                |x: usize, y: i16, z: usize, state: &BlockState, biome: Option<&str>| {
+                    let chunk_min = (chunk_min_x, chunk_min_z);
+                    if let Some(point) = filter.solid_block(chunk_min, (x, y, z), state, biome) {
+                        blocks.push(point, state);
+                    }
+                };
+
+            visit_chunk_blocks(
+                region,
+                cache,
+                raw_chunk,
+                &y_range,
+                filter.with_biomes(),
+                on_chunk.as_mut().map(|on_chunk| &mut **on_chunk as _),
+                push_block,
+            )
+        },
+    )?;
+
+    Ok(blocks)
+}
+
+/// Reads the blocks of a region file one section high slab at a time, in
+/// ascending y, and passes the blocks of every slab to `on_slab`. Chunks are
+/// read only once and kept in their compact form in between, so only the
+/// blocks of a single slab are held at once.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn read_level_file_slabs(
+    dir_entry: &DirEntry,
+    params: &ExportParams,
+    mut on_slab: impl FnMut(Vec<BlockCoordinates>) -> Result<()>,
+) -> Result<()> {
+    let mut file = RegionFile::open(dir_entry, params)?;
+    let filter = BlockFilter::new(&file, params);
+    let mut chunks = vec![];
+    file.for_each_chunk(params, |region, cache, raw_chunk, chunk_min| {
+        if let Some(chunk) = load_chunk(region, cache, raw_chunk, None)? {
+            chunks.push((chunk_min, chunk));
         }
-        chunk_min_x += file_min_x;
-        let mut chunk_min_z = (raw_chunk.z * CHUNK_BLOCKS_SIZE) as i32;
-        if file_z < 0 {
-            chunk_min_z = -chunk_min_z + FILE_BLOCKS_SIZE;
+
+        Ok(())
+    })?;
+
+    let section_size = SECTION_SIZE as i16;
+    let sections = params.start.y.div_euclid(section_size)..=params.end.y.div_euclid(section_size);
+    for section_y in sections {
+        let min_y = (section_y * section_size).max(params.start.y);
+        let max_y = (section_y * section_size + section_size - 1).min(params.end.y);
+        let mut blocks = vec![];
+
+        for (chunk_min, chunk) in &chunks {
+            chunk.visit_blocks(
+                &(min_y..=max_y),
+                filter.with_biomes(),
+                |x, y, z, state, biome| {
+                    if let Some(point) = filter.solid_block(*chunk_min, (x, y, z), state, biome) {
+                        blocks.push(point);
+                    }
+                },
+            );
         }
-        chunk_min_z += file_min_z;
+        if !blocks.is_empty() {
+            on_slab(blocks)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Block name, biome and shape filters of `ExportParams` together with the
+/// export area inside of a single region file.
+pub(crate) struct BlockFilter<'a> {
+    params: &'a ExportParams,
+    x_range: RangeInclusive<i32>,
+    z_range: RangeInclusive<i32>,
+    blocks_to_skip: Vec<&'a str>,
+    blocks_to_keep: Vec<&'a str>,
+    biomes_to_keep: Vec<&'a str>,
+}
 
-        if !should_export_chunk(&x_range, &z_range, chunk_min_x, chunk_min_z) {
-            continue;
+impl<'a> BlockFilter<'a> {
+    pub(crate) fn new(file: &RegionFile, params: &'a ExportParams) -> BlockFilter<'a> {
+        BlockFilter {
+            params,
+            x_range: file.x_range.clone(),
+            z_range: file.z_range.clone(),
+            blocks_to_skip: as_strs(&params.skip_blocks),
+            blocks_to_keep: as_strs(&params.only_blocks),
+            biomes_to_keep: as_strs(&params.only_biomes),
         }
+    }
 
-        let chunk: CurrentJavaChunk =
-            from_bytes(raw_chunk.data.as_slice()).context("Cannot parse chunk data.")?;
-
-        for y in params.start.y..=params.end.y {
-            for x in 0..CHUNK_BLOCKS_SIZE {
-                for z in 0..CHUNK_BLOCKS_SIZE {
-                    let block_x = chunk_min_x + x as i32;
-                    let block_z = chunk_min_z + z as i32;
-
-                    if x_range.contains(&block_x)
-                        && z_range.contains(&block_z)
-                        && chunk
-                            .block(x, y as isize, z)
-                            .filter(|block| {
-                                block.name() != "minecraft:air"
-                                    && !blocks_to_skip.contains(&block.name())
-                            })
-                            .is_some()
-                    {
-                        let point = BlockCoordinates::new(block_x, y, block_z);
+    /// Whether `matches` needs the biome of the block.
+    pub(crate) fn with_biomes(&self) -> bool {
+        !self.biomes_to_keep.is_empty()
+    }
 
-                        blocks.push(point);
+    /// Coordinates of the block at `x`, `y` and `z` of the chunk starting at
+    /// `chunk_min` if it is not air and passes every filter.
+    #[allow(clippy::cast_possible_truncation)]
+    fn solid_block(
+        &self,
+        (chunk_min_x, chunk_min_z): (i32, i32),
+        (x, y, z): (usize, i16, usize),
+        state: &BlockState,
+        biome: Option<&str>,
+    ) -> Option<BlockCoordinates> {
+        let point = BlockCoordinates::new(chunk_min_x + x as i32, y, chunk_min_z + z as i32);
+
+        (state.name != "minecraft:air" && self.matches(&point, &state.name, biome)).then_some(point)
+    }
+
+    /// Whether the block passes every filter. Its `y` is not checked, blocks
+    /// are expected to be read from the y range of the export area only.
+    pub(crate) fn matches(
+        &self,
+        block: &BlockCoordinates,
+        name: &str,
+        biome: Option<&str>,
+    ) -> bool {
+        self.x_range.contains(&block.x)
+            && self.z_range.contains(&block.z)
+            && (self.blocks_to_keep.is_empty() || self.blocks_to_keep.contains(&name))
+            && !self.blocks_to_skip.contains(&name)
+            && (self.biomes_to_keep.is_empty()
+                || biome.is_some_and(|biome| self.biomes_to_keep.contains(&biome)))
+            && self
+                .params
+                .shape
+                .as_ref()
+                .is_none_or(|shape| shape.contains(block))
+    }
+}
+
+/// Region file opened for reading the part of it inside of the export area.
+pub(crate) struct RegionFile {
+    path: String,
+    region: RegionReader<File>,
+    cache: Option<ChunkCache>,
+    min_x: i32,
+    min_z: i32,
+    /// Block x of the export area inside of the region file.
+    pub(crate) x_range: RangeInclusive<i32>,
+    /// Block z of the export area inside of the region file.
+    pub(crate) z_range: RangeInclusive<i32>,
+}
+
+impl RegionFile {
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn open(dir_entry: &DirEntry, params: &ExportParams) -> Result<RegionFile> {
+        let (filepath, filename) = (
+            dir_entry
+                .path()
+                .to_str()
+                .context("Cannot convert file path to str")?
+                .to_string(),
+            dir_entry
+                .file_name()
+                .to_str()
+                .context("Cannot convert file name to str")?
+                .to_string(),
+        );
+
+        let (file_x, file_z) =
+            parse_region_filename(&filename).context(format!("File {filepath} has wrong name"))?;
+        let (x_range, z_range) = get_chunk_xz_ranges(file_x, file_z, params);
+
+        let region = RegionReader::open(&dir_entry.path())?;
+        let cache = match &params.cache_dir {
+            Some(cache_dir) => {
+                let lvl_path = dir_entry.path();
+                let lvl_path = lvl_path.parent().unwrap_or_else(|| Path::new("."));
+                Some(ChunkCache::for_region(cache_dir, lvl_path, file_x, file_z)?)
+            }
+            None => None,
+        };
+
+        Ok(RegionFile {
+            path: filepath,
+            region,
+            cache,
+            min_x: file_x * FILE_CHUNKS_SIZE * CHUNK_BLOCKS_SIZE as i32,
+            min_z: file_z * FILE_CHUNKS_SIZE * CHUNK_BLOCKS_SIZE as i32,
+            x_range,
+            z_range,
+        })
+    }
+
+    /// Calls `visit` with the region, the cache, the chunk position inside of
+    /// the region and the chunk's minimum block x and z, for every chunk
+    /// overlapping the export area.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn for_each_chunk(
+        &mut self,
+        params: &ExportParams,
+        mut visit: impl FnMut(
+            &mut RegionReader<File>,
+            Option<&ChunkCache>,
+            (usize, usize),
+            (i32, i32),
+        ) -> Result<()>,
+    ) -> Result<()> {
+        for raw_chunk_z in 0..FILE_CHUNKS_SIZE as usize {
+            for raw_chunk_x in 0..FILE_CHUNKS_SIZE as usize {
+                // chunks of a region are always indexed from its minimum corner,
+                // for negative regions too
+                let chunk_min_x = self.min_x + (raw_chunk_x * CHUNK_BLOCKS_SIZE) as i32;
+                let chunk_min_z = self.min_z + (raw_chunk_z * CHUNK_BLOCKS_SIZE) as i32;
+
+                if !should_export_chunk(
+                    &self.x_range,
+                    &self.z_range,
+                    params.shape.as_ref(),
+                    chunk_min_x,
+                    chunk_min_z,
+                ) {
+                    continue;
+                }
+
+                visit(
+                    &mut self.region,
+                    self.cache.as_ref(),
+                    (raw_chunk_x, raw_chunk_z),
+                    (chunk_min_x, chunk_min_z),
+                )
+                .with_context(|| {
+                    format!(
+                        "Cannot read chunk {raw_chunk_x} {raw_chunk_z} of {}",
+                        self.path
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn as_strs(names: &[String]) -> Vec<&str> {
+    names.iter().map(std::ops::Deref::deref).collect()
+}
+
+fn visit_chunk_blocks(
+    region: &mut RegionReader<File>,
+    cache: Option<&ChunkCache>,
+    raw_chunk: (usize, usize),
+    y_range: &RangeInclusive<i16>,
+    with_biomes: bool,
+    on_chunk: Option<ChunkHook<'_>>,
+    visit: impl FnMut(usize, i16, usize, &BlockState, Option<&str>),
+) -> Result<()> {
+    if let Some(chunk) = load_chunk(region, cache, raw_chunk, on_chunk)? {
+        chunk.visit_blocks(y_range, with_biomes, visit);
+    }
+
+    Ok(())
+}
+
+/// Chunk parsed from the region file or loaded from the cache.
+enum LoadedChunk {
+    Parsed(Chunk),
+    Cached(CachedChunk),
+}
+
+/// Reads the chunk from the cache if there is one, unless `on_chunk` needs its
+/// NBT. `None` if the chunk was never generated.
+fn load_chunk(
+    region: &mut RegionReader<File>,
+    cache: Option<&ChunkCache>,
+    (chunk_x, chunk_z): (usize, usize),
+    on_chunk: Option<ChunkHook<'_>>,
+) -> Result<Option<LoadedChunk>> {
+    if let Some(on_chunk) = on_chunk {
+        let Some(data) = region.read_chunk(chunk_x, chunk_z)? else {
+            return Ok(None);
+        };
+        on_chunk(&data)?;
+        Ok(Some(LoadedChunk::Parsed(Chunk::from_bytes(&data)?)))
+    } else if let Some(cache) = cache {
+        Ok(load_cached_chunk(region, cache, chunk_x, chunk_z)?.map(LoadedChunk::Cached))
+    } else {
+        Ok(read_chunk(region, chunk_x, chunk_z)?.map(LoadedChunk::Parsed))
+    }
+}
+
+impl LoadedChunk {
+    /// Passes every block inside of `y_range` with its chunk relative x and z
+    /// to `visit`, together with its biome if `with_biomes` is set.
+    #[allow(clippy::cast_possible_truncation)]
+    fn visit_blocks(
+        &self,
+        y_range: &RangeInclusive<i16>,
+        with_biomes: bool,
+        mut visit: impl FnMut(usize, i16, usize, &BlockState, Option<&str>),
+    ) {
+        match self {
+            LoadedChunk::Parsed(chunk) => {
+                for y in y_range.clone() {
+                    let Some(section) = chunk.section(y) else {
+                        continue;
+                    };
+                    for x in 0..CHUNK_BLOCKS_SIZE {
+                        for z in 0..CHUNK_BLOCKS_SIZE {
+                            if let Some(block) = section.block(x, y, z) {
+                                let biome = if with_biomes {
+                                    section.biome(x, y, z)
+                                } else {
+                                    None
+                                };
+                                visit(x, y, z, block, biome);
+                            }
+                        }
                     }
                 }
             }
+            LoadedChunk::Cached(cached) => {
+                let biomes: FxHashMap<(usize, i16, usize), &str> = if with_biomes {
+                    cached
+                        .biome_cells()
+                        .map(|(x, y, z, biome)| ((x, y, z), biome))
+                        .collect()
+                } else {
+                    FxHashMap::default()
+                };
+
+                for (x, y, z, block) in cached.blocks().filter(|(_, y, ..)| y_range.contains(y)) {
+                    let biome = if with_biomes {
+                        let cell = (
+                            x / BIOME_CELL_SIZE,
+                            y.div_euclid(BIOME_CELL_SIZE as i16),
+                            z / BIOME_CELL_SIZE,
+                        );
+                        biomes.get(&cell).copied()
+                    } else {
+                        None
+                    };
+                    visit(x, y, z, block, biome);
+                }
+            }
         }
     }
+}
 
-    Ok(blocks)
+/// Cached chunk, parsed and stored first if the cache has no fresh entry.
+/// `None` if the chunk was never generated.
+pub(crate) fn load_cached_chunk(
+    region: &mut RegionReader<File>,
+    cache: &ChunkCache,
+    chunk_x: usize,
+    chunk_z: usize,
+) -> Result<Option<CachedChunk>> {
+    let timestamp = region.timestamp(chunk_x, chunk_z);
+    let Some(compressed) = region.read_compressed(chunk_x, chunk_z)? else {
+        return Ok(None);
+    };
+    let fingerprint = compressed.fingerprint();
+    if let Some(cached) = cache.load(chunk_x, chunk_z, timestamp, fingerprint) {
+        return Ok(Some(cached));
+    }
+
+    let chunk = Chunk::from_bytes(&compressed.decompress()?)?;
+    let cached = CachedChunk::from_chunk(&chunk, timestamp, fingerprint);
+    cache.store(chunk_x, chunk_z, &cached)?;
+
+    Ok(Some(cached))
+}
+
+pub(crate) fn read_chunk(
+    region: &mut RegionReader<File>,
+    chunk_x: usize,
+    chunk_z: usize,
+) -> Result<Option<Chunk>> {
+    let Some(data) = region.read_chunk(chunk_x, chunk_z)? else {
+        return Ok(None);
+    };
+
+    Chunk::from_bytes(&data).map(Some)
 }
 
+#[allow(clippy::cast_possible_truncation)]
 fn should_export_chunk(
     x_range: &RangeInclusive<i32>,
     z_range: &RangeInclusive<i32>,
+    shape: Option<&ExportShape>,
     chunk_min_x: i32,
     chunk_min_z: i32,
 ) -> bool {
-    let chunk_max_x = chunk_min_x + CHUNK_BLOCKS_SIZE as i32;
-    let chunk_max_z = chunk_min_z + CHUNK_BLOCKS_SIZE as i32;
-    let chunk_x_range = chunk_min_x..chunk_max_x;
-    let chunk_z_range = chunk_min_z..chunk_max_z;
-
-    let x_is_valid = chunk_x_range.contains(x_range.start())
-        || chunk_x_range.contains(x_range.end())
-        || x_range.contains(&chunk_x_range.start)
-        || x_range.contains(&chunk_x_range.end);
-    let z_is_valid = chunk_z_range.contains(z_range.start())
-        || chunk_z_range.contains(z_range.end())
-        || z_range.contains(&chunk_z_range.start)
-        || z_range.contains(&chunk_z_range.end);
-
-    x_is_valid && z_is_valid
+    let chunk_max = |chunk_min: i32| chunk_min + CHUNK_BLOCKS_SIZE as i32 - 1;
+
+    chunk_overlaps(x_range, chunk_min_x)
+        && chunk_overlaps(z_range, chunk_min_z)
+        && shape.is_none_or(|shape| {
+            shape.intersects_columns(
+                chunk_min_x,
+                chunk_min_z,
+                chunk_max(chunk_min_x),
+                chunk_max(chunk_min_z),
+            )
+        })
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn chunk_overlaps(range: &RangeInclusive<i32>, chunk_min: i32) -> bool {
+    let chunk_range = chunk_min..chunk_min + CHUNK_BLOCKS_SIZE as i32;
+
+    chunk_range.contains(range.start())
+        || chunk_range.contains(range.end())
+        || range.contains(&chunk_range.start)
+        || range.contains(&chunk_range.end)
 }
 
 fn get_chunk_xz_ranges(
@@ -177,22 +636,15 @@ fn get_chunk_xz_ranges(
 }
 
 fn get_chunk_coordinate_ranges(file_c: i32, start_c: i32, end_c: i32) -> RangeInclusive<i32> {
-    if file_c < 0 {
-        let min = cmp::max((file_c - 1) * FILE_BLOCKS_SIZE, start_c);
-        let max = cmp::min((file_c + 1) * FILE_BLOCKS_SIZE, end_c);
-
-        min..=max
-    } else {
-        let min = cmp::max(file_c * FILE_BLOCKS_SIZE, start_c);
-        let max = cmp::min((file_c + 1) * FILE_BLOCKS_SIZE, end_c);
+    let min = cmp::max(file_c * FILE_BLOCKS_SIZE, start_c);
+    let max = cmp::min((file_c + 1) * FILE_BLOCKS_SIZE - 1, end_c);
 
-        min..=max
-    }
+    min..=max
 }
 
 fn get_needed_filenames(params: &ExportParams) -> Vec<String> {
     let mut needed_files = vec![];
-    let get_file_index = |c: i32| -> i32 { (c as f32 / FILE_BLOCKS_SIZE as f32).floor() as i32 };
+    let get_file_index = |c: i32| -> i32 { c.div_euclid(FILE_BLOCKS_SIZE) };
     let start_x = get_file_index(params.start.x);
     let start_z = get_file_index(params.start.z);
     let end_x = get_file_index(params.end.x);
@@ -200,40 +652,36 @@ fn get_needed_filenames(params: &ExportParams) -> Vec<String> {
 
     for x in start_x..=end_x {
         for z in start_z..=end_z {
-            needed_files.push(format!("r.{}.{}.mca", x, z));
+            let overlaps_shape = params.shape.as_ref().is_none_or(|shape| {
+                shape.intersects_columns(
+                    x * FILE_BLOCKS_SIZE,
+                    z * FILE_BLOCKS_SIZE,
+                    (x + 1) * FILE_BLOCKS_SIZE - 1,
+                    (z + 1) * FILE_BLOCKS_SIZE - 1,
+                )
+            });
+
+            if overlaps_shape {
+                needed_files.push(format!("r.{x}.{z}.mca"));
+            }
         }
     }
-    if needed_files.is_empty() {
-        needed_files.push(format!("r.{}.{}.mca", start_x, start_z));
-    }
+
     needed_files
 }
 fn range_len(range: &RangeInclusive<i32>) -> usize {
-    let len = if *range.start() >= 0 {
-        range.end() - range.start() + 1
-    } else if *range.end() >= 0 {
-        range.end() + range.start().abs() + 1
-    } else {
-        range.start().abs() + range.end() + 1
-    };
-
-    len as usize
+    usize::try_from(range.end() - range.start() + 1).unwrap_or(0)
 }
 fn range_len_y(range: &RangeInclusive<i16>) -> usize {
-    let len = if *range.start() >= 0 {
-        range.end() - range.start() + 1
-    } else if *range.end() >= 0 {
-        range.end() + range.start().abs() + 1
-    } else {
-        range.start().abs() + range.end() + 1
-    };
-
-    len as usize
+    usize::try_from(range.end() - range.start() + 1).unwrap_or(0)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::merge::merge_blocks;
+    use crate::write::{set_section_biome, test_dir, write_world};
+    use crate::BlockSequence;
 
     #[test]
     fn read_level_export_range_1() {
@@ -283,6 +731,7 @@ mod tests {
                 start: BlockCoordinates::new(1, -63, 1),
                 end: BlockCoordinates::new(2, -63, 2),
                 skip_blocks: vec!["minecraft:stone".to_owned()],
+                ..Default::default()
             },
         );
         assert_eq!(result.unwrap(), BlockStack::from(vec![]));
@@ -295,6 +744,7 @@ mod tests {
                 start: BlockCoordinates::new(1, -64, 1),
                 end: BlockCoordinates::new(2, -63, 2),
                 skip_blocks: vec!["minecraft:stone".to_owned()],
+                ..Default::default()
             },
         );
         assert_eq!(
@@ -307,13 +757,309 @@ mod tests {
             ])
         );
     }
+    #[test]
+    fn read_level_only_blocks() {
+        let result = read_level(
+            "./assets/test_lvl",
+            ExportParams {
+                start: BlockCoordinates::new(1, -64, 1),
+                end: BlockCoordinates::new(2, -63, 2),
+                only_blocks: vec!["minecraft:stone".to_owned()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            result.unwrap(),
+            BlockStack::from(vec![
+                BlockCoordinates::new(1, -63, 1),
+                BlockCoordinates::new(1, -63, 2),
+                BlockCoordinates::new(2, -63, 1),
+                BlockCoordinates::new(2, -63, 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn read_level_only_biomes() {
+        let dir = test_dir("read_level_only_biomes");
+        let cache_dir = test_dir("read_level_only_biomes_cache");
+        let b = BlockCoordinates::new;
+        write_world(
+            &dir,
+            &[
+                (b(0, 0, 0), "minecraft:stone"),
+                (b(16, 0, 0), "minecraft:stone"),
+                (b(16, 16, 0), "minecraft:stone"),
+            ],
+        )
+        .unwrap();
+        set_section_biome(&dir, (1, 0, 0), "minecraft:desert").unwrap();
+
+        for cache_dir in [None, Some(cache_dir.clone()), Some(cache_dir)] {
+            let result = read_level(
+                &dir,
+                ExportParams {
+                    start: b(0, 0, 0),
+                    end: b(20, 20, 0),
+                    only_biomes: vec!["minecraft:desert".to_owned()],
+                    cache_dir,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(result.unwrap(), BlockStack::from(vec![b(16, 0, 0)]));
+        }
+    }
+
+    #[test]
+    fn read_level_cache_reused() {
+        let cache_dir = std::env::temp_dir().join("mca_cuboids_read_level_cache_reused");
+        let _ = fs::remove_dir_all(&cache_dir);
+        let params = ExportParams {
+            start: BlockCoordinates::new(1, -63, 5),
+            end: BlockCoordinates::new(2, -60, 6),
+            cache_dir: Some(cache_dir.to_str().unwrap().to_owned()),
+            ..Default::default()
+        };
+        let expected = BlockStack::from(vec![
+            BlockCoordinates::new(1, -63, 5),
+            BlockCoordinates::new(1, -62, 5),
+            BlockCoordinates::new(1, -61, 5),
+        ]);
+
+        assert_eq!(
+            read_level("./assets/test_lvl", params.clone()).unwrap(),
+            expected
+        );
+        let world_dirs: Vec<_> = fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(world_dirs.len(), 1);
+        assert!(world_dirs[0].join("r.0.0").join("c.0.0.nbt").exists());
+        assert_eq!(read_level("./assets/test_lvl", params).unwrap(), expected);
+    }
+    #[test]
+    fn read_level_broken_chunk_fails() {
+        let dir = test_dir("read_level_broken_chunk");
+        let b = BlockCoordinates::new;
+        write_world(&dir, &[(b(0, 0, 0), "minecraft:stone")]).unwrap();
+        // chunk 0 0 pointing far beyond the end of the file
+        let region_path = format!("{dir}/r.0.0.mca");
+        let mut region = fs::read(&region_path).unwrap();
+        region[..4].copy_from_slice(&0x00FF_FF01u32.to_be_bytes());
+        fs::write(&region_path, region).unwrap();
+
+        let result = read_level(
+            &dir,
+            ExportParams {
+                start: b(0, 0, 0),
+                end: b(5, 5, 5),
+                ..Default::default()
+            },
+        );
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Cannot read chunk 0 0"), "{error}");
+    }
+    #[test]
+    fn read_level_cache_misses_rewritten_chunk() {
+        let dir = test_dir("read_level_cache_rewritten");
+        let cache_dir = test_dir("read_level_cache_rewritten_cache");
+        let b = BlockCoordinates::new;
+        let params = ExportParams {
+            start: b(0, 0, 0),
+            end: b(5, 5, 5),
+            cache_dir: Some(cache_dir),
+            ..Default::default()
+        };
+
+        // both writes can happen within the same second
+        write_world(&dir, &[(b(0, 0, 0), "minecraft:stone")]).unwrap();
+        let first = read_level(&dir, params.clone()).unwrap();
+        write_world(&dir, &[(b(1, 0, 0), "minecraft:stone")]).unwrap();
+        let second = read_level(&dir, params).unwrap();
+
+        assert_eq!(first, BlockStack::from(vec![b(0, 0, 0)]));
+        assert_eq!(second, BlockStack::from(vec![b(1, 0, 0)]));
+    }
+    #[test]
+    fn read_level_cache_shared_by_worlds() {
+        let cache_dir = test_dir("read_level_cache_shared");
+        let b = BlockCoordinates::new;
+        let stone = test_dir("read_level_cache_shared_stone");
+        write_world(&stone, &[(b(0, 0, 0), "minecraft:stone")]).unwrap();
+        let dirt = test_dir("read_level_cache_shared_dirt");
+        write_world(&dirt, &[(b(1, 0, 0), "minecraft:dirt")]).unwrap();
+        let params = ExportParams {
+            start: b(0, 0, 0),
+            end: b(5, 5, 5),
+            cache_dir: Some(cache_dir),
+            ..Default::default()
+        };
+
+        for _ in 0..2 {
+            assert_eq!(
+                read_level(&stone, params.clone()).unwrap(),
+                BlockStack::from(vec![b(0, 0, 0)])
+            );
+            assert_eq!(
+                read_level(&dirt, params.clone()).unwrap(),
+                BlockStack::from(vec![b(1, 0, 0)])
+            );
+        }
+    }
+    #[test]
+    fn read_level_cache_stale_entry_ignored() {
+        let cache_dir = std::env::temp_dir().join("mca_cuboids_read_level_cache_stale");
+        let _ = fs::remove_dir_all(&cache_dir);
+        let cache = ChunkCache::for_region(
+            cache_dir.to_str().unwrap(),
+            Path::new("./assets/test_lvl"),
+            0,
+            0,
+        )
+        .unwrap();
+        let stale = CachedChunk::from_chunk(
+            &Chunk::from_bytes(
+                &fastnbt::to_bytes(&fastnbt::nbt!({
+                    "DataVersion": 2975,
+                    "Status": "full",
+                }))
+                .unwrap(),
+            )
+            .unwrap(),
+            0,
+            0,
+        );
+        cache.store(0, 0, &stale).unwrap();
+
+        let result = read_level(
+            "./assets/test_lvl",
+            ExportParams {
+                start: BlockCoordinates::new(1, -63, 1),
+                end: BlockCoordinates::new(2, -63, 2),
+                cache_dir: Some(cache_dir.to_str().unwrap().to_owned()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            result.unwrap(),
+            BlockStack::from(vec![
+                BlockCoordinates::new(1, -63, 1),
+                BlockCoordinates::new(1, -63, 2),
+                BlockCoordinates::new(2, -63, 1),
+                BlockCoordinates::new(2, -63, 2),
+            ])
+        );
+    }
+
+    fn quadrant_blocks() -> Vec<(BlockCoordinates, &'static str)> {
+        let b = BlockCoordinates::new;
+
+        vec![
+            (b(0, -64, 0), "minecraft:stone"),
+            (b(15, 0, 16), "minecraft:stone"),
+            (b(511, 5, 511), "minecraft:dirt"),
+            (b(512, 5, 512), "minecraft:dirt"),
+            (b(-1, -64, -1), "minecraft:stone"),
+            (b(-16, 0, -17), "minecraft:stone"),
+            (b(-512, 5, -512), "minecraft:dirt"),
+            (b(-513, 5, -513), "minecraft:dirt"),
+            (b(-700, 100, 300), "minecraft:stone"),
+            (b(300, 100, -700), "minecraft:stone"),
+            (b(-1, 319, 0), "minecraft:glass"),
+            (b(0, 319, -1), "minecraft:glass"),
+        ]
+    }
+
+    #[test]
+    fn read_level_all_quadrants() {
+        let dir = test_dir("read_level_all_quadrants");
+        let blocks = quadrant_blocks();
+        write_world(&dir, &blocks).unwrap();
+
+        let result = read_level(
+            &dir,
+            ExportParams {
+                start: BlockCoordinates::new(-1024, -64, -1024),
+                end: BlockCoordinates::new(1023, 319, 1023),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            result.unwrap(),
+            BlockStack::from(blocks.into_iter().map(|(b, _)| b).collect::<Vec<_>>())
+        );
+    }
+    #[test]
+    fn read_level_single_negative_blocks() {
+        let dir = test_dir("read_level_single_negative_blocks");
+        write_world(&dir, &quadrant_blocks()).unwrap();
+
+        for (block, _) in quadrant_blocks() {
+            let result = read_level(
+                &dir,
+                ExportParams {
+                    start: block.clone(),
+                    end: block.clone(),
+                    ..Default::default()
+                },
+            );
+
+            assert_eq!(result.unwrap(), BlockStack::from(vec![block]));
+        }
+    }
+    #[test]
+    fn read_level_truncated_region() {
+        let dir = test_dir("read_level_truncated_region");
+        fs::write(format!("{dir}/r.0.0.mca"), [0u8; 100]).unwrap();
+
+        let result = read_level(
+            &dir,
+            ExportParams {
+                start: BlockCoordinates::new(0, 0, 0),
+                end: BlockCoordinates::new(10, 10, 10),
+                ..Default::default()
+            },
+        );
+
+        assert!(result.is_err());
+    }
+    #[test]
+    fn read_level_across_zero() {
+        let dir = test_dir("read_level_across_zero");
+        let blocks: Vec<(BlockCoordinates, &str)> = (-20..20)
+            .flat_map(|x| (-3..3).map(move |z| (BlockCoordinates::new(x, 0, z), "minecraft:stone")))
+            .collect();
+        write_world(&dir, &blocks).unwrap();
+
+        let result = read_level(
+            &dir,
+            ExportParams {
+                start: BlockCoordinates::new(-5, 0, -2),
+                end: BlockCoordinates::new(4, 0, 1),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            merge_blocks(result.unwrap()),
+            vec![BlockSequence::new(
+                BlockCoordinates::new(-5, 0, -2),
+                BlockCoordinates::new(4, 0, 1)
+            )]
+        );
+    }
 
     #[test]
     fn get_chunk_ranges_1() {
         assert_eq!(get_chunk_coordinate_ranges(-1, -10, -2), -10..=-2);
         assert_eq!(get_chunk_coordinate_ranges(0, 0, 50), 0..=50);
-        assert_eq!(get_chunk_coordinate_ranges(0, -10, 1000), 0..=512);
+        assert_eq!(get_chunk_coordinate_ranges(0, -10, 1000), 0..=511);
         assert_eq!(get_chunk_coordinate_ranges(1, -10, 1000), 512..=1000);
+        assert_eq!(get_chunk_coordinate_ranges(-1, -1000, 1000), -512..=-1);
+        assert_eq!(get_chunk_coordinate_ranges(-2, -1000, 1000), -1000..=-513);
     }
     #[test]
     fn get_needed_filenames_1() {
@@ -384,6 +1130,42 @@ mod tests {
         assert_eq!(result, vec![String::from("r.-2.-2.mca")]);
     }
 
+    #[test]
+    fn get_needed_filenames_shape() {
+        let result = get_needed_filenames(&ExportParams::from_shape(ExportShape::Sphere {
+            center: BlockCoordinates::new(0, 0, 0),
+            radius: 600,
+        }));
+        // the corner regions are farther than 600 blocks away from the centre
+        assert_eq!(result.len(), 12);
+        assert!(!result.contains(&String::from("r.-2.-2.mca")));
+        assert!(result.contains(&String::from("r.-2.-1.mca")));
+    }
+    #[test]
+    fn read_level_shape() {
+        let result = read_level(
+            "./assets/test_lvl",
+            ExportParams::from_shape(ExportShape::Boxes(vec![
+                BlockSequence::new(
+                    BlockCoordinates::new(1, -63, 1),
+                    BlockCoordinates::new(1, -63, 2),
+                ),
+                BlockSequence::new(
+                    BlockCoordinates::new(1, -62, 5),
+                    BlockCoordinates::new(1, -62, 5),
+                ),
+            ])),
+        );
+        assert_eq!(
+            result.unwrap(),
+            BlockStack::from(vec![
+                BlockCoordinates::new(1, -63, 1),
+                BlockCoordinates::new(1, -63, 2),
+                BlockCoordinates::new(1, -62, 5),
+            ])
+        );
+    }
+
     #[test]
     fn range_len_1() {
         assert_eq!(range_len(&(0..=5)), 6);
@@ -0,0 +1,417 @@
+use anyhow::{bail, ensure, Context, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use rustc_hash::FxHasher;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub(crate) const REGION_CHUNKS_SIZE: usize = 32;
+const SECTOR_SIZE: u64 = 4096;
+const HEADER_ENTRIES: usize = REGION_CHUNKS_SIZE * REGION_CHUNKS_SIZE;
+const HEADER_SIZE: usize = HEADER_ENTRIES * 4;
+const CHUNK_HEADER_SIZE: u64 = 5;
+/// Vanilla saves chunks above 1 MiB into `c.x.z.mcc` files next to the region
+/// file instead, any valid chunk decompresses to far less than this.
+const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+/// Flag of the compression scheme of chunks stored in a `.mcc` file.
+const EXTERNAL_CHUNK: u8 = 0x80;
+/// Every block of lz4-java's `LZ4BlockOutputStream`, which the game uses for
+/// compression scheme 4 since 1.20.5, starts with this magic.
+const LZ4_BLOCK_MAGIC: &[u8] = b"LZ4Block";
+/// Magic, method token, compressed length, length and checksum.
+const LZ4_BLOCK_HEADER_SIZE: usize = 21;
+
+/// Reader of Anvil region files which validates every offset and length taken
+/// from the file, so malformed files result in errors instead of panics.
+pub(crate) struct RegionReader<S> {
+    stream: S,
+    len: u64,
+    locations: Vec<u32>,
+    timestamps: Vec<u32>,
+    /// Directory and coordinates of the region file, to find `.mcc` files.
+    external: Option<(PathBuf, i32, i32)>,
+}
+
+impl RegionReader<File> {
+    /// Opens the region file at `path`, reading its oversized chunks from the
+    /// `.mcc` files next to it.
+    pub(crate) fn open(path: &Path) -> Result<RegionReader<File>> {
+        let file = File::open(path).context(format!("Cannot open file {}", path.display()))?;
+        let mut region = RegionReader::new(file)
+            .context(format!("Cannot read region file {}", path.display()))?;
+        region.external = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_region_filename)
+            .map(|(x, z)| {
+                let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                (dir.to_path_buf(), x, z)
+            });
+
+        Ok(region)
+    }
+}
+
+impl<S: Read + Seek> RegionReader<S> {
+    pub(crate) fn new(mut stream: S) -> Result<RegionReader<S>> {
+        let len = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(0))?;
+
+        let mut header = vec![0u8; HEADER_SIZE * 2];
+        stream
+            .read_exact(&mut header)
+            .context("Region file is shorter than its header")?;
+        let entries: Vec<u32> = header
+            .chunks_exact(4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        let (locations, timestamps) = entries.split_at(HEADER_ENTRIES);
+
+        Ok(RegionReader {
+            stream,
+            len,
+            locations: locations.to_vec(),
+            timestamps: timestamps.to_vec(),
+            external: None,
+        })
+    }
+
+    /// Modification time of the chunk as saved by the game, `0` if unknown.
+    pub(crate) fn timestamp(&self, chunk_x: usize, chunk_z: usize) -> u32 {
+        header_index(chunk_x, chunk_z).map_or(0, |index| self.timestamps[index])
+    }
+
+    /// Uncompressed NBT of the chunk, `None` if the chunk was never generated.
+    pub(crate) fn read_chunk(&mut self, chunk_x: usize, chunk_z: usize) -> Result<Option<Vec<u8>>> {
+        self.read_compressed(chunk_x, chunk_z)?
+            .map(|chunk| chunk.decompress())
+            .transpose()
+    }
+
+    /// Chunk as stored in the region file, `None` if the chunk was never
+    /// generated.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn read_compressed(
+        &mut self,
+        chunk_x: usize,
+        chunk_z: usize,
+    ) -> Result<Option<CompressedChunk>> {
+        let index = header_index(chunk_x, chunk_z).context(format!(
+            "Chunk {chunk_x} {chunk_z} is outside of the region"
+        ))?;
+        let location = self.locations[index];
+        if location == 0 {
+            return Ok(None);
+        }
+
+        let offset = u64::from(location >> 8) * SECTOR_SIZE;
+        let sectors = u64::from(location & 0xFF);
+        ensure!(
+            offset >= (HEADER_SIZE * 2) as u64 && offset + CHUNK_HEADER_SIZE <= self.len,
+            "Chunk {chunk_x} {chunk_z} points outside of the region file"
+        );
+
+        let mut chunk_header = [0u8; CHUNK_HEADER_SIZE as usize];
+        self.stream.seek(SeekFrom::Start(offset))?;
+        self.stream.read_exact(&mut chunk_header)?;
+        let length = u64::from(u32::from_be_bytes([
+            chunk_header[0],
+            chunk_header[1],
+            chunk_header[2],
+            chunk_header[3],
+        ]));
+        ensure!(
+            length >= 1
+                && offset + 4 + length <= self.len
+                && 4 + length <= sectors.max(1) * SECTOR_SIZE,
+            "Chunk {chunk_x} {chunk_z} has invalid length {length}"
+        );
+
+        let scheme = chunk_header[4];
+        if scheme & EXTERNAL_CHUNK != 0 {
+            return self
+                .read_external(chunk_x, chunk_z, scheme & !EXTERNAL_CHUNK)
+                .map(Some);
+        }
+
+        let mut data = vec![];
+        (&mut self.stream).take(length - 1).read_to_end(&mut data)?;
+
+        Ok(Some(CompressedChunk {
+            chunk_x,
+            chunk_z,
+            scheme,
+            data,
+        }))
+    }
+
+    /// Chunk stored in the `c.x.z.mcc` file next to the region file, named
+    /// after the absolute chunk coordinates.
+    #[allow(clippy::cast_possible_truncation)]
+    fn read_external(&self, chunk_x: usize, chunk_z: usize, scheme: u8) -> Result<CompressedChunk> {
+        let Some((dir, region_x, region_z)) = &self.external else {
+            bail!("Chunk {chunk_x} {chunk_z} is stored outside of the region file");
+        };
+        let region_size = REGION_CHUNKS_SIZE as i32;
+        let path = dir.join(format!(
+            "c.{}.{}.mcc",
+            region_x * region_size + chunk_x as i32,
+            region_z * region_size + chunk_z as i32
+        ));
+        let mut data = vec![];
+        File::open(&path)
+            .context(format!("Cannot open file {}", path.display()))?
+            .take(MAX_CHUNK_SIZE + 1)
+            .read_to_end(&mut data)
+            .context(format!("Cannot read file {}", path.display()))?;
+        ensure!(
+            data.len() as u64 <= MAX_CHUNK_SIZE,
+            "Chunk {chunk_x} {chunk_z} is too large"
+        );
+
+        Ok(CompressedChunk {
+            chunk_x,
+            chunk_z,
+            scheme,
+            data,
+        })
+    }
+}
+
+/// Chunk as stored in the region file, before decompression.
+pub(crate) struct CompressedChunk {
+    chunk_x: usize,
+    chunk_z: usize,
+    scheme: u8,
+    data: Vec<u8>,
+}
+
+impl CompressedChunk {
+    /// Hash of the stored bytes, which changes whenever the chunk is saved
+    /// with different content.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut hasher = FxHasher::default();
+        hasher.write_u8(self.scheme);
+        hasher.write(&self.data);
+
+        hasher.finish()
+    }
+
+    /// Uncompressed NBT of the chunk.
+    pub(crate) fn decompress(&self) -> Result<Vec<u8>> {
+        let (chunk_x, chunk_z) = (self.chunk_x, self.chunk_z);
+        let compressed = self.data.as_slice();
+        let mut data = vec![];
+        let read = match self.scheme {
+            1 => GzDecoder::new(compressed)
+                .take(MAX_CHUNK_SIZE + 1)
+                .read_to_end(&mut data),
+            2 => ZlibDecoder::new(compressed)
+                .take(MAX_CHUNK_SIZE + 1)
+                .read_to_end(&mut data),
+            3 => compressed.take(MAX_CHUNK_SIZE + 1).read_to_end(&mut data),
+            4 => decompress_lz4_blocks(compressed, &mut data).map(|()| data.len()),
+            scheme => bail!("Chunk {chunk_x} {chunk_z} has unsupported compression {scheme}"),
+        };
+        read.context(format!("Cannot decompress chunk {chunk_x} {chunk_z}"))?;
+        ensure!(
+            data.len() as u64 <= MAX_CHUNK_SIZE,
+            "Chunk {chunk_x} {chunk_z} is too large"
+        );
+
+        Ok(data)
+    }
+}
+
+/// Decompresses the blocks of an lz4-java `LZ4BlockOutputStream` up to its
+/// empty end block. Checksums are not verified, broken chunks fail to parse.
+fn decompress_lz4_blocks(mut input: &[u8], data: &mut Vec<u8>) -> std::io::Result<()> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+    while !input.is_empty() {
+        if input.len() < LZ4_BLOCK_HEADER_SIZE || !input.starts_with(LZ4_BLOCK_MAGIC) {
+            return Err(invalid("invalid LZ4 block header"));
+        }
+        let int = |at: usize| {
+            let bytes = [input[at], input[at + 1], input[at + 2], input[at + 3]];
+            usize::try_from(u32::from_le_bytes(bytes)).unwrap_or(usize::MAX)
+        };
+        let (method, compressed_len, len) = (input[8] & 0xF0, int(9), int(13));
+        input = &input[LZ4_BLOCK_HEADER_SIZE..];
+        if len == 0 {
+            break;
+        }
+        if compressed_len > input.len() || (data.len() + len) as u64 > MAX_CHUNK_SIZE {
+            return Err(invalid("invalid LZ4 block length"));
+        }
+
+        let (block, rest) = input.split_at(compressed_len);
+        match method {
+            0x10 if compressed_len == len => data.extend_from_slice(block),
+            0x20 => {
+                let start = data.len();
+                data.resize(start + len, 0);
+                let read = lz4_flex::block::decompress_into(block, &mut data[start..])
+                    .map_err(|error| invalid(&error.to_string()))?;
+                if read != len {
+                    return Err(invalid("LZ4 block is shorter than its length"));
+                }
+            }
+            _ => return Err(invalid("unknown LZ4 block method")),
+        }
+        input = rest;
+    }
+
+    Ok(())
+}
+
+fn header_index(chunk_x: usize, chunk_z: usize) -> Option<usize> {
+    (chunk_x < REGION_CHUNKS_SIZE && chunk_z < REGION_CHUNKS_SIZE)
+        .then_some(chunk_z * REGION_CHUNKS_SIZE + chunk_x)
+}
+
+/// Region coordinates from a file name like `r.-1.2.mca`.
+pub(crate) fn parse_region_filename(filename: &str) -> Option<(i32, i32)> {
+    let (x, z) = filename
+        .strip_prefix("r.")?
+        .strip_suffix(".mca")?
+        .split_once('.')?;
+
+    Some((x.parse().ok()?, z.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn region_with_chunk(offset: u32, sectors: u8, chunk: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_SIZE * 2];
+        data[..4].copy_from_slice(&((offset << 8) | u32::from(sectors)).to_be_bytes());
+        data[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&42u32.to_be_bytes());
+        data.extend_from_slice(chunk);
+        data.resize(data.len().max((offset as usize + 1) * 4096), 0);
+
+        data
+    }
+
+    #[test]
+    fn parse_region_filenames() {
+        assert_eq!(parse_region_filename("r.-1.2.mca"), Some((-1, 2)));
+        assert_eq!(parse_region_filename("r.0.0.mca"), Some((0, 0)));
+        assert_eq!(parse_region_filename("r.mca"), None);
+        assert_eq!(parse_region_filename("r.1.mca"), None);
+        assert_eq!(parse_region_filename("r.1.2.3.mca"), None);
+        assert_eq!(parse_region_filename("r..mca"), None);
+        assert_eq!(parse_region_filename("r.1.2.mcc"), None);
+        assert_eq!(parse_region_filename(""), None);
+    }
+    #[test]
+    fn read_uncompressed_chunk() {
+        let region = region_with_chunk(2, 1, &[0, 0, 0, 4, 3, 1, 2, 3]);
+        let mut reader = RegionReader::new(Cursor::new(region)).unwrap();
+
+        assert_eq!(reader.timestamp(0, 0), 42);
+        assert_eq!(reader.read_chunk(0, 0).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(reader.read_chunk(1, 0).unwrap(), None);
+        assert!(reader.read_chunk(32, 0).is_err());
+    }
+    #[test]
+    fn reject_malformed_regions() {
+        assert!(RegionReader::new(Cursor::new(vec![0u8; 100])).is_err());
+
+        for (offset, sectors, chunk) in [
+            // inside of the header
+            (1, 1, vec![0, 0, 0, 4, 3, 1, 2, 3]),
+            // zero length
+            (2, 1, vec![0, 0, 0, 0, 3]),
+            // longer than the file
+            (2, 1, vec![0, 0, 255, 0, 3]),
+            // unknown compression
+            (2, 1, vec![0, 0, 0, 2, 9, 0]),
+            // broken zlib stream
+            (2, 1, vec![0, 0, 0, 3, 2, 1, 2]),
+        ] {
+            let mut reader =
+                RegionReader::new(Cursor::new(region_with_chunk(offset, sectors, &chunk))).unwrap();
+
+            assert!(reader.read_chunk(0, 0).is_err(), "{chunk:?}");
+        }
+
+        let mut data = region_with_chunk(2, 1, &[0, 0, 0, 4, 3, 1, 2, 3]);
+        data[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut reader = RegionReader::new(Cursor::new(data)).unwrap();
+        assert!(reader.read_chunk(0, 0).is_err());
+    }
+    #[test]
+    fn read_lz4_chunk() {
+        let nbt: Vec<u8> = (0..2000u16).map(|i| (i % 7) as u8).collect();
+        let compressed = lz4_flex::block::compress(&nbt);
+        let mut stream = lz4_block(0x20, compressed.len(), nbt.len());
+        stream.extend_from_slice(&compressed);
+        stream.extend_from_slice(&lz4_block(0x10, 3, 3));
+        stream.extend_from_slice(&[7, 8, 9]);
+        stream.extend_from_slice(&lz4_block(0x10, 0, 0));
+
+        let mut chunk = (u32::try_from(stream.len()).unwrap() + 1)
+            .to_be_bytes()
+            .to_vec();
+        chunk.push(4);
+        chunk.extend_from_slice(&stream);
+        let mut reader = RegionReader::new(Cursor::new(region_with_chunk(2, 1, &chunk))).unwrap();
+
+        let mut expected = nbt;
+        expected.extend_from_slice(&[7, 8, 9]);
+        assert_eq!(reader.read_chunk(0, 0).unwrap(), Some(expected));
+    }
+    #[test]
+    fn read_external_chunk() {
+        let dir = crate::write::test_dir("region_external_chunk");
+        let region = region_with_chunk(2, 1, &[0, 0, 0, 1, 3 | EXTERNAL_CHUNK]);
+        let mut data = region.clone();
+        data[4..8].copy_from_slice(&((2 << 8) | 1u32).to_be_bytes());
+        let path = Path::new(&dir).join("r.-1.2.mca");
+        std::fs::write(&path, data).unwrap();
+        std::fs::write(Path::new(&dir).join("c.-32.64.mcc"), [1, 2, 3]).unwrap();
+
+        let mut reader = RegionReader::open(&path).unwrap();
+        assert_eq!(reader.read_chunk(0, 0).unwrap(), Some(vec![1, 2, 3]));
+        // c.-31.64.mcc is missing
+        assert!(reader.read_chunk(1, 0).is_err());
+        // without a path there is no way to find the file
+        let mut reader = RegionReader::new(Cursor::new(region)).unwrap();
+        assert!(reader.read_chunk(0, 0).is_err());
+    }
+    #[test]
+    fn reject_malformed_lz4_chunks() {
+        let mut truncated = lz4_block(0x20, 10, 20);
+        truncated.extend_from_slice(&[0; 5]);
+        let mut unknown_method = lz4_block(0x30, 1, 1);
+        unknown_method.push(0);
+        let mut too_large = lz4_block(0x20, 1, usize::try_from(MAX_CHUNK_SIZE).unwrap() + 1);
+        too_large.push(0);
+
+        for stream in [b"LZ4".to_vec(), truncated, unknown_method, too_large] {
+            let mut chunk = (u32::try_from(stream.len()).unwrap() + 1)
+                .to_be_bytes()
+                .to_vec();
+            chunk.push(4);
+            chunk.extend_from_slice(&stream);
+            let mut reader =
+                RegionReader::new(Cursor::new(region_with_chunk(2, 1, &chunk))).unwrap();
+
+            assert!(reader.read_chunk(0, 0).is_err(), "{stream:?}");
+        }
+    }
+
+    fn lz4_block(method: u8, compressed_len: usize, len: usize) -> Vec<u8> {
+        let mut block = LZ4_BLOCK_MAGIC.to_vec();
+        block.push(method);
+        block.extend_from_slice(&u32::try_from(compressed_len).unwrap().to_le_bytes());
+        block.extend_from_slice(&u32::try_from(len).unwrap().to_le_bytes());
+        block.extend_from_slice(&0u32.to_le_bytes());
+
+        block
+    }
+}
@@ -0,0 +1,384 @@
+use crate::block_sequence::BlockSequence;
+use crate::BlockCoordinates;
+use serde::{Deserialize, Serialize};
+
+/// Area of the world to export when an axis-aligned box is not enough.
+///
+/// A shape narrows the `start`/`end` box of `ExportParams`: only blocks inside
+/// of both are exported. `ExportParams::from_shape` sets the box to the shape
+/// bounds, so usually there is no need to fill it by hand.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportShape {
+    /// Blocks whose distance to `center` is at most `radius` blocks.
+    Sphere {
+        center: BlockCoordinates,
+        radius: u32,
+    },
+    /// Vertical cylinder around the `center_x`/`center_z` column.
+    Cylinder {
+        center_x: i32,
+        center_z: i32,
+        radius: u32,
+        min_y: i16,
+        max_y: i16,
+    },
+    /// Polygon footprint given by its `(x, z)` vertices in order, extruded from
+    /// `min_y` to `max_y`. A block is inside if its column centre is.
+    Polygon {
+        points: Vec<(i32, i32)>,
+        min_y: i16,
+        max_y: i16,
+    },
+    /// Whole chunks given by their `(x, z)` chunk coordinates.
+    Chunks {
+        chunks: Vec<(i32, i32)>,
+        min_y: i16,
+        max_y: i16,
+    },
+    /// Union of axis-aligned boxes.
+    Boxes(Vec<BlockSequence>),
+}
+
+impl ExportShape {
+    /// The smallest box containing the shape, `None` if the shape is empty.
+    #[must_use]
+    pub fn bounds(&self) -> Option<BlockSequence> {
+        let bounds = |min_x: i32, min_z: i32, max_x: i32, max_z: i32, min_y: i16, max_y: i16| {
+            BlockSequence::new(
+                BlockCoordinates::new(min_x, min_y, min_z),
+                BlockCoordinates::new(max_x, max_y, max_z),
+            )
+        };
+
+        match self {
+            ExportShape::Sphere { center, radius } => {
+                let r = clamp_i32(i64::from(*radius));
+                let ry = clamp_i16(i64::from(*radius));
+
+                Some(bounds(
+                    center.x.saturating_sub(r),
+                    center.z.saturating_sub(r),
+                    center.x.saturating_add(r),
+                    center.z.saturating_add(r),
+                    center.y.saturating_sub(ry),
+                    center.y.saturating_add(ry),
+                ))
+            }
+            ExportShape::Cylinder {
+                center_x,
+                center_z,
+                radius,
+                min_y,
+                max_y,
+            } => {
+                let r = clamp_i32(i64::from(*radius));
+
+                Some(bounds(
+                    center_x.saturating_sub(r),
+                    center_z.saturating_sub(r),
+                    center_x.saturating_add(r),
+                    center_z.saturating_add(r),
+                    *min_y,
+                    *max_y,
+                ))
+            }
+            ExportShape::Polygon {
+                points,
+                min_y,
+                max_y,
+            } => {
+                let (min_x, max_x) = min_max(points.iter().map(|(x, _)| *x))?;
+                let (min_z, max_z) = min_max(points.iter().map(|(_, z)| *z))?;
+
+                Some(bounds(min_x, min_z, max_x, max_z, *min_y, *max_y))
+            }
+            ExportShape::Chunks {
+                chunks,
+                min_y,
+                max_y,
+            } => {
+                let (min_x, max_x) = min_max(chunks.iter().map(|(x, _)| *x))?;
+                let (min_z, max_z) = min_max(chunks.iter().map(|(_, z)| *z))?;
+
+                Some(bounds(
+                    min_x * 16,
+                    min_z * 16,
+                    max_x * 16 + 15,
+                    max_z * 16 + 15,
+                    *min_y,
+                    *max_y,
+                ))
+            }
+            ExportShape::Boxes(boxes) => boxes.iter().cloned().reduce(|a, b| a.union(&b)),
+        }
+    }
+
+    #[must_use]
+    pub fn contains(&self, block: &BlockCoordinates) -> bool {
+        match self {
+            ExportShape::Sphere { center, radius } => {
+                let dx = i64::from(block.x) - i64::from(center.x);
+                let dy = i64::from(block.y) - i64::from(center.y);
+                let dz = i64::from(block.z) - i64::from(center.z);
+
+                dx * dx + dy * dy + dz * dz <= i64::from(*radius) * i64::from(*radius)
+            }
+            ExportShape::Cylinder {
+                center_x,
+                center_z,
+                radius,
+                min_y,
+                max_y,
+            } => {
+                let dx = i64::from(block.x) - i64::from(*center_x);
+                let dz = i64::from(block.z) - i64::from(*center_z);
+
+                (*min_y..=*max_y).contains(&block.y)
+                    && dx * dx + dz * dz <= i64::from(*radius) * i64::from(*radius)
+            }
+            ExportShape::Polygon {
+                points,
+                min_y,
+                max_y,
+            } => {
+                (*min_y..=*max_y).contains(&block.y)
+                    && polygon_contains(points, f64::from(block.x) + 0.5, f64::from(block.z) + 0.5)
+            }
+            ExportShape::Chunks {
+                chunks,
+                min_y,
+                max_y,
+            } => {
+                (*min_y..=*max_y).contains(&block.y)
+                    && chunks.contains(&(block.x.div_euclid(16), block.z.div_euclid(16)))
+            }
+            ExportShape::Boxes(boxes) => boxes.iter().any(|b| b.contains(block)),
+        }
+    }
+
+    /// Whether any block column of the inclusive `x`/`z` rectangle may contain
+    /// blocks of the shape. Used to skip region files and chunks.
+    #[must_use]
+    pub fn intersects_columns(&self, min_x: i32, min_z: i32, max_x: i32, max_z: i32) -> bool {
+        let distance_squared = |cx: i32, cz: i32| {
+            let dx = i64::from(cx.clamp(min_x, max_x)) - i64::from(cx);
+            let dz = i64::from(cz.clamp(min_z, max_z)) - i64::from(cz);
+
+            dx * dx + dz * dz
+        };
+
+        match self {
+            ExportShape::Sphere { center, radius } => {
+                distance_squared(center.x, center.z) <= i64::from(*radius) * i64::from(*radius)
+            }
+            ExportShape::Cylinder {
+                center_x,
+                center_z,
+                radius,
+                ..
+            } => distance_squared(*center_x, *center_z) <= i64::from(*radius) * i64::from(*radius),
+            ExportShape::Polygon { points, .. } => {
+                polygon_intersects_rect(points, min_x, min_z, max_x, max_z)
+            }
+            ExportShape::Chunks { chunks, .. } => chunks.iter().any(|(x, z)| {
+                x * 16 <= max_x && x * 16 + 15 >= min_x && z * 16 <= max_z && z * 16 + 15 >= min_z
+            }),
+            ExportShape::Boxes(boxes) => boxes.iter().any(|b| {
+                b.start.x <= max_x && b.end.x >= min_x && b.start.z <= max_z && b.end.z >= min_z
+            }),
+        }
+    }
+}
+
+fn min_max(values: impl Iterator<Item = i32>) -> Option<(i32, i32)> {
+    values.fold(None, |acc, v| match acc {
+        None => Some((v, v)),
+        Some((min, max)) => Some((min.min(v), max.max(v))),
+    })
+}
+
+fn clamp_i32(value: i64) -> i32 {
+    i32::try_from(value).unwrap_or(i32::MAX)
+}
+
+fn clamp_i16(value: i64) -> i16 {
+    i16::try_from(value).unwrap_or(i16::MAX)
+}
+
+/// Even-odd rule point in polygon test.
+fn polygon_contains(points: &[(i32, i32)], x: f64, z: f64) -> bool {
+    let mut inside = false;
+
+    for (i, &(x1, z1)) in points.iter().enumerate() {
+        let (x2, z2) = points[(i + 1) % points.len()];
+        let (x1, z1, x2, z2) = (f64::from(x1), f64::from(z1), f64::from(x2), f64::from(z2));
+
+        if (z1 > z) != (z2 > z) && x < (x2 - x1) * (z - z1) / (z2 - z1) + x1 {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// Whether the polygon overlaps the area covered by the block columns of the
+/// inclusive rectangle.
+fn polygon_intersects_rect(
+    points: &[(i32, i32)],
+    min_x: i32,
+    min_z: i32,
+    max_x: i32,
+    max_z: i32,
+) -> bool {
+    if points.is_empty() {
+        return false;
+    }
+
+    let (low_x, low_z) = (f64::from(min_x), f64::from(min_z));
+    let (high_x, high_z) = (f64::from(max_x) + 1.0, f64::from(max_z) + 1.0);
+
+    let vertex_inside = points.iter().any(|&(x, z)| {
+        (low_x..=high_x).contains(&f64::from(x)) && (low_z..=high_z).contains(&f64::from(z))
+    });
+    let rect_inside = polygon_contains(
+        points,
+        f64::midpoint(low_x, high_x),
+        f64::midpoint(low_z, high_z),
+    );
+    let edge_crosses = points.iter().enumerate().any(|(i, &(x1, z1))| {
+        let (x2, z2) = points[(i + 1) % points.len()];
+
+        segment_intersects_rect(
+            (f64::from(x1), f64::from(z1)),
+            (f64::from(x2), f64::from(z2)),
+            (low_x, low_z),
+            (high_x, high_z),
+        )
+    });
+
+    vertex_inside || rect_inside || edge_crosses
+}
+
+/// Liang-Barsky clipping of a segment against a rectangle.
+fn segment_intersects_rect(
+    a: (f64, f64),
+    b: (f64, f64),
+    low: (f64, f64),
+    high: (f64, f64),
+) -> bool {
+    let (dx, dz) = (b.0 - a.0, b.1 - a.1);
+    let mut t_min = 0.0_f64;
+    let mut t_max = 1.0_f64;
+
+    for (p, q) in [
+        (-dx, a.0 - low.0),
+        (dx, high.0 - a.0),
+        (-dz, a.1 - low.1),
+        (dz, high.1 - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return false;
+            }
+            continue;
+        }
+
+        let t = q / p;
+        if p < 0.0 {
+            t_min = t_min.max(t);
+        } else {
+            t_max = t_max.min(t);
+        }
+        if t_min > t_max {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(x: i32, y: i16, z: i32) -> BlockCoordinates {
+        BlockCoordinates::new(x, y, z)
+    }
+
+    #[test]
+    fn sphere() {
+        let shape = ExportShape::Sphere {
+            center: block(0, 0, 0),
+            radius: 2,
+        };
+
+        assert!(shape.contains(&block(0, 2, 0)));
+        assert!(shape.contains(&block(1, 1, 1)));
+        assert!(!shape.contains(&block(2, 1, 0)));
+        assert_eq!(
+            shape.bounds(),
+            Some(BlockSequence::new(block(-2, -2, -2), block(2, 2, 2)))
+        );
+        assert!(shape.intersects_columns(2, 0, 10, 10));
+        assert!(!shape.intersects_columns(2, 2, 10, 10));
+    }
+    #[test]
+    fn cylinder() {
+        let shape = ExportShape::Cylinder {
+            center_x: 10,
+            center_z: 10,
+            radius: 3,
+            min_y: -64,
+            max_y: 0,
+        };
+
+        assert!(shape.contains(&block(13, -64, 10)));
+        assert!(!shape.contains(&block(13, 1, 10)));
+        assert!(!shape.contains(&block(13, 0, 12)));
+        assert!(!shape.intersects_columns(14, 0, 20, 20));
+    }
+    #[test]
+    fn polygon() {
+        // right triangle with the right angle at the origin
+        let shape = ExportShape::Polygon {
+            points: vec![(0, 0), (10, 0), (0, 10)],
+            min_y: 0,
+            max_y: 5,
+        };
+
+        assert!(shape.contains(&block(1, 0, 1)));
+        assert!(shape.contains(&block(4, 5, 4)));
+        assert!(!shape.contains(&block(5, 0, 5)));
+        assert!(!shape.contains(&block(-1, 0, 1)));
+        assert!(shape.intersects_columns(-100, -100, 100, 100));
+        assert!(shape.intersects_columns(2, 2, 3, 3));
+        assert!(shape.intersects_columns(9, -5, 20, 0));
+        assert!(!shape.intersects_columns(7, 7, 20, 20));
+    }
+    #[test]
+    fn chunks_and_boxes() {
+        let chunks = ExportShape::Chunks {
+            chunks: vec![(-1, 0)],
+            min_y: 0,
+            max_y: 0,
+        };
+        assert!(chunks.contains(&block(-16, 0, 15)));
+        assert!(!chunks.contains(&block(0, 0, 15)));
+        assert_eq!(
+            chunks.bounds(),
+            Some(BlockSequence::new(block(-16, 0, 0), block(-1, 0, 15)))
+        );
+
+        let boxes = ExportShape::Boxes(vec![
+            BlockSequence::new(block(0, 0, 0), block(1, 1, 1)),
+            BlockSequence::new(block(5, 0, 5), block(6, 1, 6)),
+        ]);
+        assert!(boxes.contains(&block(6, 1, 5)));
+        assert!(!boxes.contains(&block(3, 0, 3)));
+        assert!(!boxes.intersects_columns(2, 2, 4, 4));
+        assert_eq!(
+            boxes.bounds(),
+            Some(BlockSequence::new(block(0, 0, 0), block(6, 1, 6)))
+        );
+    }
+}
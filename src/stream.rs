@@ -0,0 +1,180 @@
+use crate::block_stack::BlockStack;
+use crate::merge::Merger;
+use crate::read::{get_region_files, read_level_file_slabs};
+use crate::{BlockSequence, ExportParams};
+use anyhow::{bail, Result};
+use std::num::NonZeroUsize;
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+/// Finished cuboids buffered between the region workers and the callback.
+const CHANNEL_CAPACITY: usize = 4096;
+/// Region files read and merged at the same time at most, whatever the number
+/// of cores.
+const MAX_REGIONS_IN_FLIGHT: usize = 4;
+
+/// Reads and merges the level one region file per worker at a time, feeding
+/// the merger one section high slab at a time, so only the chunks of a few
+/// regions and the blocks of a single slab per region are kept in memory.
+/// Cuboids are passed to `on_cuboid` as soon as they cannot grow anymore.
+pub(crate) fn stream_level(
+    lvl_path: &str,
+    params: ExportParams,
+    mut on_cuboid: impl FnMut(BlockSequence),
+) -> Result<()> {
+    let params = params.normalized()?;
+    let files = get_region_files(lvl_path, &params)?;
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(MAX_REGIONS_IN_FLIGHT)
+        .min(files.len())
+        .max(1);
+    let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            let sender = sender.clone();
+            let (files, params) = (&files, &params);
+
+            scope.spawn(move || {
+                for dir_entry in files.iter().skip(worker).step_by(workers) {
+                    let mut merger = Merger::default();
+                    let read = read_level_file_slabs(dir_entry, params, |blocks| {
+                        for (y, plate) in BlockStack::from(blocks).plates() {
+                            for cuboid in merger.add_plate(y, plate) {
+                                // the receiver is gone after an error, stop reading
+                                if sender.send(Ok(cuboid)).is_err() {
+                                    bail!("Cuboid receiver is gone");
+                                }
+                            }
+                        }
+
+                        Ok(())
+                    });
+                    if let Err(error) = read {
+                        let _ = sender.send(Err(error));
+                        return;
+                    }
+
+                    for cuboid in merger.finish() {
+                        if sender.send(Ok(cuboid)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for cuboid in receiver {
+            on_cuboid(cuboid?);
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::read_level;
+    use crate::verify;
+    use crate::write::{test_dir, write_world};
+    use crate::BlockCoordinates;
+
+    #[test]
+    fn stream_level_covers_all_blocks() {
+        let params = ExportParams {
+            start: BlockCoordinates::new(-10, -64, -10),
+            end: BlockCoordinates::new(20, 0, 20),
+            ..Default::default()
+        };
+        let mut cuboids = vec![];
+        stream_level("./assets/test_lvl", params.clone(), |c| cuboids.push(c)).unwrap();
+
+        let blocks: Vec<BlockCoordinates> = read_level("./assets/test_lvl", params)
+            .unwrap()
+            .plates()
+            .into_iter()
+            .flat_map(|(_, plate)| plate.rows())
+            .flat_map(|(_, row)| row)
+            .collect();
+        assert!(!cuboids.is_empty());
+        assert_eq!(verify(&cuboids, &blocks), Ok(()));
+    }
+    #[test]
+    fn stream_level_splits_at_regions() {
+        let dir = test_dir("stream_level_splits_at_regions");
+        let b = BlockCoordinates::new;
+        let blocks: Vec<(BlockCoordinates, &str)> = (500..530)
+            .map(|x| (b(x, 0, 0), "minecraft:stone"))
+            .collect();
+        write_world(&dir, &blocks).unwrap();
+
+        let mut cuboids = vec![];
+        stream_level(
+            &dir,
+            ExportParams {
+                start: b(0, 0, 0),
+                end: b(1000, 0, 0),
+                ..Default::default()
+            },
+            |c| cuboids.push(c),
+        )
+        .unwrap();
+        cuboids.sort_by_key(|c| c.start.x);
+
+        assert_eq!(
+            cuboids,
+            vec![
+                BlockSequence::new(b(500, 0, 0), b(511, 0, 0)),
+                BlockSequence::new(b(512, 0, 0), b(529, 0, 0)),
+            ]
+        );
+    }
+    #[test]
+    fn stream_level_merges_across_slabs() {
+        let dir = test_dir("stream_level_merges_across_slabs");
+        let b = BlockCoordinates::new;
+        let blocks: Vec<(BlockCoordinates, &str)> = (-20..40)
+            .flat_map(|y| {
+                [
+                    (b(1, y, 1), "minecraft:stone"),
+                    (b(2, y, 1), "minecraft:stone"),
+                ]
+            })
+            .collect();
+        write_world(&dir, &blocks).unwrap();
+
+        let mut cuboids = vec![];
+        stream_level(
+            &dir,
+            ExportParams {
+                start: b(0, -64, 0),
+                end: b(10, 100, 10),
+                ..Default::default()
+            },
+            |c| cuboids.push(c),
+        )
+        .unwrap();
+
+        assert_eq!(cuboids, vec![BlockSequence::new(b(1, -20, 1), b(2, 39, 1))]);
+    }
+    #[test]
+    fn stream_level_reports_errors() {
+        let dir = test_dir("stream_level_reports_errors");
+        std::fs::write(format!("{dir}/r.0.0.mca"), [0u8; 100]).unwrap();
+
+        let result = stream_level(
+            &dir,
+            ExportParams {
+                start: BlockCoordinates::new(0, 0, 0),
+                end: BlockCoordinates::new(10, 10, 10),
+                ..Default::default()
+            },
+            |_| {},
+        );
+
+        assert!(result.is_err());
+    }
+}
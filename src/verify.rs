@@ -0,0 +1,151 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::block_sequence::BlockSequence;
+use crate::BlockCoordinates;
+
+/// Difference between the blocks covered by cuboids and the expected blocks.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct CoverageMismatch {
+    /// Expected blocks which no cuboid covers.
+    pub missing: Vec<BlockCoordinates>,
+    /// Covered blocks which were not expected.
+    pub extra: Vec<BlockCoordinates>,
+    /// Blocks covered by more than one cuboid.
+    pub overlapping: Vec<BlockCoordinates>,
+}
+
+impl Display for CoverageMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cuboids do not cover the blocks exactly: {} missing, {} extra, {} overlapping",
+            self.missing.len(),
+            self.extra.len(),
+            self.overlapping.len()
+        )
+    }
+}
+
+impl Error for CoverageMismatch {}
+
+/// Checks that `cuboids` cover every block of `blocks` exactly once and nothing
+/// else, by rasterizing the cuboids back to blocks.
+///
+/// # Errors
+///
+/// Will return `Err` listing the mismatching blocks if the coverage is not exact.
+pub fn verify(
+    cuboids: &[BlockSequence],
+    blocks: &[BlockCoordinates],
+) -> Result<(), CoverageMismatch> {
+    let mut covered: FxHashMap<BlockCoordinates, u32> = FxHashMap::default();
+    for block in cuboids.iter().flat_map(BlockSequence::blocks) {
+        *covered.entry(block).or_default() += 1;
+    }
+    let expected: FxHashSet<&BlockCoordinates> = blocks.iter().collect();
+
+    let mut mismatch = CoverageMismatch {
+        missing: blocks
+            .iter()
+            .filter(|block| !covered.contains_key(block))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    for (block, count) in covered {
+        if count > 1 {
+            mismatch.overlapping.push(block.clone());
+        }
+        if !expected.contains(&block) {
+            mismatch.extra.push(block);
+        }
+    }
+
+    if mismatch == CoverageMismatch::default() {
+        Ok(())
+    } else {
+        Err(mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stack::BlockStack;
+    use crate::merge::merge_blocks;
+    use proptest::prelude::*;
+
+    /// Up to 300 distinct blocks in a 40x20x40 area around the origin.
+    fn blocks() -> impl Strategy<Value = Vec<BlockCoordinates>> {
+        prop::collection::hash_set((-20..20i32, -10..10i16, -20..20i32), 1..300).prop_map(
+            |blocks| {
+                blocks
+                    .into_iter()
+                    .map(|(x, y, z)| BlockCoordinates::new(x, y, z))
+                    .collect()
+            },
+        )
+    }
+
+    #[test]
+    fn verify_reports_mismatch() {
+        let b = BlockCoordinates::new;
+        let cuboids = vec![
+            BlockSequence::new(b(0, 0, 0), b(1, 0, 0)),
+            BlockSequence::new(b(1, 0, 0), b(1, 0, 0)),
+        ];
+
+        assert_eq!(
+            verify(&cuboids, &[b(0, 0, 0), b(5, 5, 5)]),
+            Err(CoverageMismatch {
+                missing: vec![b(5, 5, 5)],
+                extra: vec![b(1, 0, 0)],
+                overlapping: vec![b(1, 0, 0)],
+            })
+        );
+        assert_eq!(verify(&cuboids[..1], &[b(1, 0, 0), b(0, 0, 0)]), Ok(()));
+    }
+
+    proptest! {
+        #[test]
+        fn merge_blocks_preserves_coverage(blocks in blocks()) {
+            let cuboids = merge_blocks(BlockStack::from(blocks.clone()));
+
+            prop_assert_eq!(verify(&cuboids, &blocks), Ok(()));
+        }
+        #[test]
+        fn merge_blocks_does_not_overlap(blocks in blocks()) {
+            let cuboids = merge_blocks(BlockStack::from(blocks));
+
+            for (i, a) in cuboids.iter().enumerate() {
+                for b in &cuboids[i + 1..] {
+                    prop_assert!(!a.intersects(b), "{a:?} overlaps {b:?}");
+                }
+            }
+        }
+        #[test]
+        fn merge_blocks_does_not_add_cuboids(blocks in blocks()) {
+            let count = blocks.len();
+            let cuboids = merge_blocks(BlockStack::from(blocks));
+
+            prop_assert!(cuboids.len() <= count);
+        }
+        #[test]
+        fn merge_blocks_merges_boxes(
+            start in (-32..32i32, -32..32i16, -32..32i32),
+            size in (0..8i32, 0..8i16, 0..8i32),
+        ) {
+            let cuboid = BlockSequence::new(
+                BlockCoordinates::new(start.0, start.1, start.2),
+                BlockCoordinates::new(start.0 + size.0, start.1 + size.1, start.2 + size.2),
+            );
+
+            prop_assert_eq!(
+                merge_blocks(BlockStack::from(cuboid.blocks().collect::<Vec<_>>())),
+                vec![cuboid]
+            );
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! `MagicaVoxel` `.vox` export.
+//!
+//! `MagicaVoxel` is z up, blocks are stored at `(x, -z, y)` to keep the world's
+//! handedness. Models are limited to 256 voxels per axis, so the world is cut
+//! into 256³ tiles, each written as its own model and placed by a transform
+//! node of the scene graph. Every block name gets one of the 255 palette
+//! colors, derived from the name.
+
+use crate::read::read_level_typed;
+use crate::{BlockCoordinates, BlockSequence, ExportParams};
+use anyhow::{ensure, Result};
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+const VOX_VERSION: i32 = 150;
+const MODEL_SIZE: i32 = 256;
+const MAX_COLORS: usize = 255;
+const ROOT_NODE: i32 = 0;
+const GROUP_NODE: i32 = 1;
+
+/// Writes the blocks of the level in `params` as a `.vox` file.
+///
+/// # Errors
+///
+/// Same as [`crate::export_cuboids`], or if the area holds more than 255
+/// different blocks or writing to `out` fails.
+pub fn export_vox(lvl_path: &str, params: ExportParams, out: impl Write) -> Result<()> {
+    let blocks = read_level_typed(lvl_path, params)?;
+
+    write_voxels(
+        out,
+        blocks
+            .iter()
+            .map(|(block, name)| (vox_position(block), name)),
+    )
+}
+
+/// Writes typed cuboids, e.g. a [`crate::TypedCuboidDiff`], as a `.vox` file.
+///
+/// # Errors
+///
+/// Will return `Err` if the cuboids hold more than 255 different blocks or
+/// writing to `out` fails.
+pub fn write_vox(out: impl Write, cuboids: &[(BlockSequence, &str)]) -> Result<()> {
+    write_voxels(
+        out,
+        cuboids.iter().flat_map(|(cuboid, name)| {
+            cuboid
+                .blocks()
+                .map(move |block| (vox_position(&block), *name))
+        }),
+    )
+}
+
+fn vox_position(block: &BlockCoordinates) -> [i32; 3] {
+    [block.x, -block.z, i32::from(block.y)]
+}
+
+/// Voxels of a tile, kept in world positions until the bounds are known.
+struct Model {
+    min: [i32; 3],
+    max: [i32; 3],
+    voxels: Vec<([i32; 3], u8)>,
+}
+
+impl Model {
+    fn size(&self) -> [i32; 3] {
+        [0, 1, 2].map(|axis| self.max[axis] - self.min[axis] + 1)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_voxels<'a>(
+    mut out: impl Write,
+    voxels: impl Iterator<Item = ([i32; 3], &'a str)>,
+) -> Result<()> {
+    let mut names: Vec<&str> = vec![];
+    let mut indexes: FxHashMap<&str, u8> = FxHashMap::default();
+    let mut tiles: BTreeMap<[i32; 3], Model> = BTreeMap::new();
+
+    for (position, name) in voxels {
+        let index = if let Some(index) = indexes.get(name) {
+            *index
+        } else {
+            ensure!(
+                names.len() < MAX_COLORS,
+                "A .vox palette holds at most {MAX_COLORS} blocks"
+            );
+            names.push(name);
+            indexes.insert(name, names.len() as u8);
+            names.len() as u8
+        };
+
+        let tile = position.map(|c| c.div_euclid(MODEL_SIZE));
+        let model = tiles.entry(tile).or_insert(Model {
+            min: position,
+            max: position,
+            voxels: vec![],
+        });
+        model.min = [0, 1, 2].map(|axis| model.min[axis].min(position[axis]));
+        model.max = [0, 1, 2].map(|axis| model.max[axis].max(position[axis]));
+        model.voxels.push((position, index));
+    }
+
+    // palette indexes in name order, so the output does not depend on the
+    // order blocks were read in
+    let mut sorted: Vec<(usize, &str)> = names.iter().copied().enumerate().collect();
+    sorted.sort_by_key(|(_, name)| *name);
+    let mut remap = vec![0u8; names.len() + 1];
+    for (color, (index, _)) in sorted.iter().enumerate() {
+        remap[index + 1] = color as u8 + 1;
+    }
+    for model in tiles.values_mut() {
+        for (_, index) in &mut model.voxels {
+            *index = remap[usize::from(*index)];
+        }
+        model.voxels.sort_unstable();
+    }
+
+    let mut children = vec![];
+    for model in tiles.values() {
+        chunk(&mut children, *b"SIZE", |c| {
+            model.size().iter().for_each(|s| put_i32(c, *s));
+        });
+        chunk(&mut children, *b"XYZI", |c| {
+            put_i32(c, model.voxels.len() as i32);
+            for (position, index) in &model.voxels {
+                // tiles never span more than 256 voxels per axis
+                let [x, y, z] =
+                    [0, 1, 2].map(|axis| (position[axis] - model.min[axis]).cast_unsigned() as u8);
+                c.extend_from_slice(&[x, y, z, *index]);
+            }
+        });
+    }
+    write_scene(&mut children, &tiles);
+    chunk(&mut children, *b"RGBA", |c| {
+        for color in 0..256 {
+            let rgba = sorted
+                .get(color)
+                .map_or([0, 0, 0, 255], |(_, name)| block_color(name));
+            c.extend_from_slice(&rgba);
+        }
+    });
+
+    out.write_all(b"VOX ")?;
+    out.write_all(&VOX_VERSION.to_le_bytes())?;
+    out.write_all(b"MAIN")?;
+    out.write_all(&0i32.to_le_bytes())?;
+    out.write_all(&(children.len() as i32).to_le_bytes())?;
+    out.write_all(&children)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Root transform, a group and a transform plus shape per model, placing
+/// every model at its bounds. `MagicaVoxel` positions the center of a model.
+#[allow(clippy::cast_possible_truncation)]
+fn write_scene(out: &mut Vec<u8>, tiles: &BTreeMap<[i32; 3], Model>) {
+    let node_of_model = |model: usize| GROUP_NODE + 1 + model as i32 * 2;
+
+    chunk(out, *b"nTRN", |c| {
+        transform(c, ROOT_NODE, GROUP_NODE, -1, None);
+    });
+    chunk(out, *b"nGRP", |c| {
+        put_i32(c, GROUP_NODE);
+        put_dict(c, &[]);
+        put_i32(c, tiles.len() as i32);
+        (0..tiles.len()).for_each(|model| put_i32(c, node_of_model(model)));
+    });
+    for (index, model) in tiles.values().enumerate() {
+        let size = model.size();
+        let [x, y, z] = [0, 1, 2].map(|axis| model.min[axis] + size[axis] / 2);
+        let node = node_of_model(index);
+
+        chunk(out, *b"nTRN", |c| {
+            transform(c, node, node + 1, 0, Some(&format!("{x} {y} {z}")));
+        });
+        chunk(out, *b"nSHP", |c| {
+            put_i32(c, node + 1);
+            put_dict(c, &[]);
+            put_i32(c, 1);
+            put_i32(c, index as i32);
+            put_dict(c, &[]);
+        });
+    }
+}
+
+fn transform(out: &mut Vec<u8>, node: i32, child: i32, layer: i32, translation: Option<&str>) {
+    put_i32(out, node);
+    put_dict(out, &[]);
+    put_i32(out, child);
+    put_i32(out, -1);
+    put_i32(out, layer);
+    put_i32(out, 1);
+    match translation {
+        Some(translation) => put_dict(out, &[("_t", translation)]),
+        None => put_dict(out, &[]),
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn chunk(out: &mut Vec<u8>, id: [u8; 4], content: impl FnOnce(&mut Vec<u8>)) {
+    let mut bytes = vec![];
+    content(&mut bytes);
+
+    out.extend_from_slice(&id);
+    put_i32(out, bytes.len() as i32);
+    put_i32(out, 0);
+    out.extend_from_slice(&bytes);
+}
+
+fn put_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn put_dict(out: &mut Vec<u8>, entries: &[(&str, &str)]) {
+    put_i32(out, entries.len() as i32);
+    for (key, value) in entries {
+        for string in [key, value] {
+            put_i32(out, string.len() as i32);
+            out.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+
+/// Color of common materials, any other block gets a color hashed from its name.
+fn block_color(name: &str) -> [u8; 4] {
+    const MATERIALS: [(&str, [u8; 4]); 10] = [
+        ("water", [63, 118, 228, 255]),
+        ("lava", [207, 92, 15, 255]),
+        ("grass", [95, 159, 53, 255]),
+        ("leaves", [60, 122, 37, 255]),
+        ("dirt", [134, 96, 67, 255]),
+        ("sand", [219, 207, 163, 255]),
+        ("snow", [249, 254, 254, 255]),
+        ("log", [102, 81, 51, 255]),
+        ("planks", [162, 130, 78, 255]),
+        ("stone", [125, 125, 125, 255]),
+    ];
+    let path = name.rsplit(':').next().unwrap_or(name);
+    if let Some((_, color)) = MATERIALS
+        .iter()
+        .find(|(material, _)| path.contains(material))
+    {
+        return *color;
+    }
+
+    // FNV-1a
+    let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    let [r, g, b, _] = hash.to_le_bytes();
+    [r, g, b, 255]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cuboid(start: (i32, i16, i32), end: (i32, i16, i32)) -> BlockSequence {
+        BlockSequence::new(
+            BlockCoordinates::new(start.0, start.1, start.2),
+            BlockCoordinates::new(end.0, end.1, end.2),
+        )
+    }
+
+    /// Ids and contents of the children of the `MAIN` chunk.
+    fn chunks(vox: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&vox[..4], b"VOX ");
+        assert_eq!(&vox[8..12], b"MAIN");
+        let read_len = |at: usize| u32::from_le_bytes(vox[at..at + 4].try_into().unwrap()) as usize;
+        assert_eq!(read_len(16), vox.len() - 20);
+
+        let mut chunks = vec![];
+        let mut at = 20;
+        while at < vox.len() {
+            let len = read_len(at + 4);
+            let id = String::from_utf8(vox[at..at + 4].to_vec()).unwrap();
+            chunks.push((id, vox[at + 12..at + 12 + len].to_vec()));
+            at += 12 + len;
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn write_single_model() {
+        let mut out = vec![];
+        write_vox(
+            &mut out,
+            &[
+                (cuboid((0, 0, 0), (1, 0, 0)), "minecraft:stone"),
+                (cuboid((0, 1, -2), (0, 1, -2)), "minecraft:dirt"),
+            ],
+        )
+        .unwrap();
+        let chunks = chunks(&out);
+        let ids: Vec<&str> = chunks.iter().map(|(id, _)| id.as_str()).collect();
+
+        assert_eq!(
+            ids,
+            ["SIZE", "XYZI", "nTRN", "nGRP", "nTRN", "nSHP", "RGBA"]
+        );
+        assert_eq!(chunks[0].1, [2, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0].to_vec());
+        // dirt sorts before stone
+        assert_eq!(
+            chunks[1].1,
+            [3, 0, 0, 0, 0, 0, 0, 2, 0, 2, 1, 1, 1, 0, 0, 2].to_vec()
+        );
+        assert_eq!(&chunks[6].1[..8], &[134, 96, 67, 255, 125, 125, 125, 255]);
+    }
+    #[test]
+    fn split_large_areas_into_models() {
+        let mut out = vec![];
+        write_vox(
+            &mut out,
+            &[(cuboid((-10, 0, 0), (300, 0, 0)), "minecraft:stone")],
+        )
+        .unwrap();
+        let chunks = chunks(&out);
+        let sizes: Vec<&Vec<u8>> = chunks
+            .iter()
+            .filter(|(id, _)| id == "SIZE")
+            .map(|(_, content)| content)
+            .collect();
+
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes[0][..4], 10i32.to_le_bytes());
+        assert_eq!(sizes[1][..4], 256i32.to_le_bytes());
+        assert_eq!(sizes[2][..4], 45i32.to_le_bytes());
+        assert_eq!(chunks.iter().filter(|(id, _)| id == "nSHP").count(), 3);
+    }
+    #[test]
+    fn reject_too_many_blocks() {
+        let names: Vec<String> = (0..256).map(|i| format!("minecraft:block_{i}")).collect();
+        let cuboids: Vec<(BlockSequence, &str)> = (0..)
+            .zip(&names)
+            .map(|(x, name)| (cuboid((x, 0, 0), (x, 0, 0)), name.as_str()))
+            .collect();
+
+        assert!(write_vox(vec![], &cuboids).is_err());
+        assert!(write_vox(vec![], &cuboids[1..]).is_ok());
+    }
+}
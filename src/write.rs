@@ -0,0 +1,800 @@
+use anyhow::{bail, ensure, Context, Result};
+use fastanvil::Region;
+use fastnbt::{LongArray, Value};
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::region::RegionReader;
+use crate::{BlockCoordinates, BlockSequence};
+
+/// 1.18.2
+const DATA_VERSION: i32 = 2975;
+const SECTION_BLOCKS: usize = 16 * 16 * 16;
+/// Offset of the chunk timestamps in the region file header.
+const TIMESTAMPS_OFFSET: u64 = 4096;
+
+#[derive(Serialize)]
+struct ChunkNbt {
+    #[serde(rename = "DataVersion")]
+    data_version: i32,
+    #[serde(rename = "Status")]
+    status: &'static str,
+    #[serde(rename = "xPos")]
+    x_pos: i32,
+    #[serde(rename = "yPos")]
+    y_pos: i32,
+    #[serde(rename = "zPos")]
+    z_pos: i32,
+    sections: Vec<SectionNbt>,
+}
+
+#[derive(Serialize)]
+struct SectionNbt {
+    #[serde(rename = "Y")]
+    y: i8,
+    block_states: BlockStatesNbt,
+    biomes: BiomesNbt,
+}
+
+#[derive(Serialize)]
+struct BlockStatesNbt {
+    palette: Vec<BlockStateNbt>,
+    data: LongArray,
+}
+
+#[derive(Serialize, PartialEq)]
+struct BlockStateNbt {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Properties", skip_serializing_if = "BTreeMap::is_empty")]
+    properties: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct BiomesNbt {
+    palette: Vec<String>,
+}
+
+/// How region files which already exist are treated.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum WriteMode {
+    /// Region files touched by the blocks are overwritten, everything else in
+    /// them is air.
+    #[default]
+    Replace,
+    /// The blocks replace the blocks of existing chunks, everything else of
+    /// the world is kept.
+    Overlay,
+}
+
+/// Writes `r.x.z.mca` region files into `lvl_path` containing exactly `blocks`,
+/// which makes it possible to build test worlds without launching the game.
+///
+/// Blocks are given as block states in the `/setblock` syntax, e.g.
+/// `minecraft:stone` or `minecraft:oak_stairs[facing=east,half=top]`. Region
+/// files touched by `blocks` are overwritten; everything else is air.
+///
+/// # Errors
+///
+/// Will return `Err` if a block state cannot be parsed or a region file cannot
+/// be written.
+pub fn write_world(lvl_path: &str, blocks: &[(BlockCoordinates, &str)]) -> Result<()> {
+    write_cuboids(lvl_path, &block_cuboids(blocks), WriteMode::Replace)
+}
+
+/// Places `blocks` into the existing world at `lvl_path`, see
+/// [`WriteMode::Overlay`]. `minecraft:air` removes blocks. Heightmaps and light
+/// of the changed chunks are left for the game to recompute.
+///
+/// # Errors
+///
+/// Same as [`write_world`], or if an existing chunk cannot be read or was
+/// saved before 1.18.
+pub fn overlay_world(lvl_path: &str, blocks: &[(BlockCoordinates, &str)]) -> Result<()> {
+    write_cuboids(lvl_path, &block_cuboids(blocks), WriteMode::Overlay)
+}
+
+/// Writes typed cuboids, e.g. the output of a diff or a programmatic build,
+/// as blocks of the world at `lvl_path`. Later cuboids replace the blocks of
+/// earlier ones where they overlap.
+///
+/// # Errors
+///
+/// Same as [`write_world`] and [`overlay_world`].
+pub fn write_cuboids(
+    lvl_path: &str,
+    cuboids: &[(BlockSequence, &str)],
+    mode: WriteMode,
+) -> Result<()> {
+    let mut chunks: FxHashMap<(i32, i32), Vec<(&BlockSequence, &str)>> = FxHashMap::default();
+    for (cuboid, state) in cuboids {
+        for chunk_z in cuboid.start.z.div_euclid(16)..=cuboid.end.z.div_euclid(16) {
+            for chunk_x in cuboid.start.x.div_euclid(16)..=cuboid.end.x.div_euclid(16) {
+                chunks
+                    .entry((chunk_x, chunk_z))
+                    .or_default()
+                    .push((cuboid, state));
+            }
+        }
+    }
+
+    let mut regions: FxHashMap<(i32, i32), Vec<(i32, i32)>> = FxHashMap::default();
+    for chunk in chunks.keys() {
+        regions
+            .entry((chunk.0.div_euclid(32), chunk.1.div_euclid(32)))
+            .or_default()
+            .push(*chunk);
+    }
+
+    for ((region_x, region_z), region_chunks) in regions {
+        let path = Path::new(lvl_path).join(format!("r.{region_x}.{region_z}.mca"));
+        let overlay =
+            mode == WriteMode::Overlay && path.metadata().is_ok_and(|metadata| metadata.len() > 0);
+        let existing = if overlay {
+            read_chunks(&path, &region_chunks)?
+        } else {
+            FxHashMap::default()
+        };
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(!overlay)
+            .open(&path)
+            .context(format!("Cannot create region file {}", path.display()))?;
+        let mut region = if overlay {
+            Region::from_stream(file).context(format!("Cannot read region {}", path.display()))?
+        } else {
+            Region::new(file).context("Cannot create region")?
+        };
+
+        for &(chunk_x, chunk_z) in &region_chunks {
+            let sections = section_fills(chunk_x, chunk_z, &chunks[&(chunk_x, chunk_z)])?;
+            let bytes = match existing.get(&(chunk_x, chunk_z)) {
+                Some(existing) => overlay_chunk(existing, sections)
+                    .context(format!("Cannot overlay chunk {chunk_x} {chunk_z}"))?,
+                None => fastnbt::to_bytes(&chunk_nbt(chunk_x, chunk_z, sections)?)
+                    .context("Cannot serialize chunk")?,
+            };
+
+            region
+                .write_chunk(
+                    chunk_x.rem_euclid(32) as usize,
+                    chunk_z.rem_euclid(32) as usize,
+                    &bytes,
+                )
+                .context(format!("Cannot write chunk to {}", path.display()))?;
+        }
+
+        let mut file = region.into_inner()?;
+        write_timestamps(&mut file, &region_chunks)
+            .context(format!("Cannot write timestamps to {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn block_cuboids<'a>(blocks: &[(BlockCoordinates, &'a str)]) -> Vec<(BlockSequence, &'a str)> {
+    blocks
+        .iter()
+        .map(|(block, state)| (BlockSequence::new(block.clone(), block.clone()), *state))
+        .collect()
+}
+
+/// Sets the header timestamps of `chunks` to now. fastanvil leaves them
+/// unchanged, but the game and the chunk cache rely on them.
+fn write_timestamps(file: &mut File, chunks: &[(i32, i32)]) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let now = u32::try_from(now).unwrap_or(u32::MAX);
+
+    for (chunk_x, chunk_z) in chunks {
+        let index = chunk_z.rem_euclid(32) as u64 * 32 + chunk_x.rem_euclid(32) as u64;
+        file.seek(SeekFrom::Start(TIMESTAMPS_OFFSET + index * 4))?;
+        file.write_all(&now.to_be_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Uncompressed NBT of the generated ones of `chunks`.
+fn read_chunks(path: &Path, chunks: &[(i32, i32)]) -> Result<FxHashMap<(i32, i32), Vec<u8>>> {
+    let mut region = RegionReader::open(path)?;
+
+    let mut existing = FxHashMap::default();
+    for (chunk_x, chunk_z) in chunks {
+        let chunk = region.read_chunk(
+            chunk_x.rem_euclid(32) as usize,
+            chunk_z.rem_euclid(32) as usize,
+        )?;
+        if let Some(chunk) = chunk {
+            existing.insert((*chunk_x, *chunk_z), chunk);
+        }
+    }
+
+    Ok(existing)
+}
+
+/// Replaces blocks of an existing chunk, keeping all of its other data.
+fn overlay_chunk(existing: &[u8], sections: SectionFills) -> Result<Vec<u8>> {
+    let mut chunk: Value = fastnbt::from_bytes(existing)?;
+    let Value::Compound(root) = &mut chunk else {
+        bail!("Chunk is not a compound");
+    };
+    let Some(Value::List(existing_sections)) = root.get_mut("sections") else {
+        bail!("Chunk has no sections, it was probably saved before 1.18");
+    };
+
+    for (y, fills) in sections {
+        let section = existing_sections.iter_mut().find(
+            |section| matches!(section, Value::Compound(s) if s.get("Y") == Some(&Value::Byte(y))),
+        );
+        match section {
+            Some(Value::Compound(section)) => overlay_section(section, &fills)?,
+            _ => existing_sections.push(fastnbt::to_value(section_nbt(y, &fills)?)?),
+        }
+    }
+
+    root.remove("Heightmaps");
+    root.insert("isLightOn".to_owned(), Value::Byte(0));
+
+    Ok(fastnbt::to_bytes(&chunk)?)
+}
+
+fn overlay_section(
+    section: &mut HashMap<String, Value>,
+    fills: &[(BlockSequence, &str)],
+) -> Result<()> {
+    let Some(Value::Compound(block_states)) = section.get_mut("block_states") else {
+        bail!("Section has no block states");
+    };
+    let Some(Value::List(mut palette)) = block_states.remove("palette") else {
+        bail!("Section has no block palette");
+    };
+    let mut states = match block_states.get("data") {
+        Some(Value::LongArray(data)) => unpack_states(data, palette.len())?,
+        _ => vec![0; SECTION_BLOCKS],
+    };
+
+    fill_section(&mut states, &mut palette, fills, |state| {
+        Ok(fastnbt::to_value(state)?)
+    })?;
+
+    // drops the states which are not used anymore
+    let mut used: Vec<Option<usize>> = vec![None; palette.len()];
+    let mut compact = vec![];
+    for state in &mut states {
+        *state = *used[*state].get_or_insert_with(|| {
+            compact.push(palette[*state].clone());
+            compact.len() - 1
+        });
+    }
+
+    block_states.insert(
+        "data".to_owned(),
+        Value::LongArray(pack_states(&states, palette_bits(compact.len()))),
+    );
+    block_states.insert("palette".to_owned(), Value::List(compact));
+    section.remove("BlockLight");
+    section.remove("SkyLight");
+
+    Ok(())
+}
+
+fn chunk_nbt(chunk_x: i32, chunk_z: i32, sections: SectionFills) -> Result<ChunkNbt> {
+    Ok(ChunkNbt {
+        data_version: DATA_VERSION,
+        status: "full",
+        x_pos: chunk_x,
+        y_pos: sections.keys().next().map_or(0, |y| i32::from(*y)),
+        z_pos: chunk_z,
+        sections: sections
+            .into_iter()
+            .map(|(y, fills)| section_nbt(y, &fills))
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// Parts of the cuboids inside of every section of a chunk, by section y.
+type SectionFills<'a> = BTreeMap<i8, Vec<(BlockSequence, &'a str)>>;
+
+/// Clips the cuboids touching a chunk to each of its sections, keeping their
+/// order.
+fn section_fills<'a>(
+    chunk_x: i32,
+    chunk_z: i32,
+    cuboids: &[(&BlockSequence, &'a str)],
+) -> Result<SectionFills<'a>> {
+    let mut sections: SectionFills = BTreeMap::new();
+    for (cuboid, state) in cuboids {
+        for section_y in cuboid.start.y.div_euclid(16)..=cuboid.end.y.div_euclid(16) {
+            let section = BlockSequence::new(
+                BlockCoordinates::new(chunk_x * 16, section_y * 16, chunk_z * 16),
+                BlockCoordinates::new(chunk_x * 16 + 15, section_y * 16 + 15, chunk_z * 16 + 15),
+            );
+            if let Some(fill) = cuboid.intersection(&section) {
+                sections
+                    .entry(i8::try_from(section_y)?)
+                    .or_default()
+                    .push((fill, state));
+            }
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Sets the palette indexes of the blocks of every fill, adding their block
+/// states to the palette. Each block state is parsed once per fill.
+fn fill_section<T: PartialEq>(
+    states: &mut [usize],
+    palette: &mut Vec<T>,
+    fills: &[(BlockSequence, &str)],
+    to_state: impl Fn(BlockStateNbt) -> Result<T>,
+) -> Result<()> {
+    for (fill, state) in fills {
+        let state = to_state(parse_block_state(state)?)?;
+        let state = palette.iter().position(|s| *s == state).unwrap_or_else(|| {
+            palette.push(state);
+            palette.len() - 1
+        });
+
+        for y in fill.start.y.rem_euclid(16)..=fill.end.y.rem_euclid(16) {
+            for z in fill.start.z.rem_euclid(16)..=fill.end.z.rem_euclid(16) {
+                let row = usize::try_from(i32::from(y) * 256 + z * 16)?;
+                let start = row + usize::try_from(fill.start.x.rem_euclid(16))?;
+                let end = row + usize::try_from(fill.end.x.rem_euclid(16))?;
+                states[start..=end].fill(state);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn section_nbt(y: i8, fills: &[(BlockSequence, &str)]) -> Result<SectionNbt> {
+    let mut palette = vec![parse_block_state("minecraft:air")?];
+    let mut states = vec![0usize; SECTION_BLOCKS];
+    fill_section(&mut states, &mut palette, fills, Ok)?;
+
+    let data = pack_states(&states, palette_bits(palette.len()));
+
+    Ok(SectionNbt {
+        y,
+        block_states: BlockStatesNbt { palette, data },
+        biomes: BiomesNbt {
+            palette: vec!["minecraft:plains".to_owned()],
+        },
+    })
+}
+
+/// Bits per block of a block state palette, at least 4 like the game uses.
+fn palette_bits(palette_len: usize) -> usize {
+    ((usize::BITS - (palette_len.max(2) - 1).leading_zeros()) as usize).max(4)
+}
+
+/// Packs palette indexes into longs, without spreading an index over two longs.
+fn pack_states(states: &[usize], bits: usize) -> LongArray {
+    LongArray::new(
+        states
+            .chunks(64 / bits)
+            .map(|states| {
+                states.iter().enumerate().fold(0u64, |long, (i, state)| {
+                    long | (*state as u64) << (i * bits)
+                }) as i64
+            })
+            .collect(),
+    )
+}
+
+/// Reverse of [`pack_states`].
+#[allow(clippy::cast_possible_truncation)]
+fn unpack_states(data: &LongArray, palette_len: usize) -> Result<Vec<usize>> {
+    let bits = palette_bits(palette_len);
+    let per_long = 64 / bits;
+    ensure!(
+        data.len() * per_long >= SECTION_BLOCKS,
+        "Section has {} longs of block states",
+        data.len()
+    );
+
+    (0..SECTION_BLOCKS)
+        .map(|index| {
+            let long = data[index / per_long].cast_unsigned();
+            let state = (long >> (index % per_long * bits)) as usize & ((1 << bits) - 1);
+            ensure!(
+                state < palette_len,
+                "Block state {state} is not in the palette"
+            );
+            Ok(state)
+        })
+        .collect()
+}
+
+fn parse_block_state(state: &str) -> Result<BlockStateNbt> {
+    let (name, properties) = match state.split_once('[') {
+        Some((name, properties)) => (
+            name,
+            properties
+                .strip_suffix(']')
+                .context(format!("Block state {state} misses closing bracket"))?,
+        ),
+        None => (state, ""),
+    };
+
+    Ok(BlockStateNbt {
+        name: name.to_owned(),
+        properties: properties
+            .split(',')
+            .filter(|property| !property.is_empty())
+            .map(|property| {
+                property
+                    .split_once('=')
+                    .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                    .context(format!(
+                        "Block state {state} has invalid property {property}"
+                    ))
+            })
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// Applies `edit` to the root compound of a chunk written before.
+#[cfg(test)]
+fn edit_chunk(
+    lvl_path: &str,
+    (chunk_x, chunk_z): (i32, i32),
+    edit: impl FnOnce(&mut HashMap<String, Value>) -> Result<()>,
+) -> Result<()> {
+    let path = Path::new(lvl_path).join(format!(
+        "r.{}.{}.mca",
+        chunk_x.div_euclid(32),
+        chunk_z.div_euclid(32)
+    ));
+    let existing = read_chunks(&path, &[(chunk_x, chunk_z)])?;
+    let mut chunk: Value = fastnbt::from_bytes(&existing[&(chunk_x, chunk_z)])?;
+    let Value::Compound(root) = &mut chunk else {
+        bail!("Chunk is not a compound");
+    };
+    edit(root)?;
+
+    let file = File::options().read(true).write(true).open(&path)?;
+    let mut region = Region::from_stream(file)?;
+    region.write_chunk(
+        chunk_x.rem_euclid(32) as usize,
+        chunk_z.rem_euclid(32) as usize,
+        &fastnbt::to_bytes(&chunk)?,
+    )?;
+
+    write_timestamps(&mut region.into_inner()?, &[(chunk_x, chunk_z)])
+}
+
+/// Sets the biome of a whole section of a chunk written before.
+#[cfg(test)]
+pub(crate) fn set_section_biome(
+    lvl_path: &str,
+    (chunk_x, section_y, chunk_z): (i32, i8, i32),
+    biome: &str,
+) -> Result<()> {
+    edit_chunk(lvl_path, (chunk_x, chunk_z), |root| {
+        let Some(Value::List(sections)) = root.get_mut("sections") else {
+            bail!("Chunk has no sections");
+        };
+        for section in sections {
+            if let Value::Compound(section) = section {
+                if section.get("Y") == Some(&Value::Byte(section_y)) {
+                    section.insert(
+                        "biomes".to_owned(),
+                        fastnbt::to_value(BiomesNbt {
+                            palette: vec![biome.to_owned()],
+                        })?,
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Sets the light of a whole section of a chunk written before, with the
+/// block light given per block index of the section.
+#[cfg(test)]
+pub(crate) fn set_section_light(
+    lvl_path: &str,
+    (chunk_x, section_y, chunk_z): (i32, i8, i32),
+    block_light: impl Fn(usize) -> u8,
+    sky_light: u8,
+) -> Result<()> {
+    let pack = |light: &dyn Fn(usize) -> u8| {
+        let bytes = (0..SECTION_BLOCKS / 2)
+            .map(|index| (light(index * 2) | (light(index * 2 + 1) << 4)).cast_signed())
+            .collect();
+        Value::ByteArray(fastnbt::ByteArray::new(bytes))
+    };
+
+    edit_chunk(lvl_path, (chunk_x, chunk_z), |root| {
+        let Some(Value::List(sections)) = root.get_mut("sections") else {
+            bail!("Chunk has no sections");
+        };
+        for section in sections {
+            if let Value::Compound(section) = section {
+                if section.get("Y") == Some(&Value::Byte(section_y)) {
+                    section.insert("BlockLight".to_owned(), pack(&block_light));
+                    section.insert("SkyLight".to_owned(), pack(&|_| sky_light));
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Sets the block entities of a chunk written before.
+#[cfg(test)]
+pub(crate) fn set_block_entities(
+    lvl_path: &str,
+    chunk: (i32, i32),
+    block_entities: Vec<Value>,
+) -> Result<()> {
+    edit_chunk(lvl_path, chunk, |root| {
+        root.insert("block_entities".to_owned(), Value::List(block_entities));
+        Ok(())
+    })
+}
+
+/// Writes the entities of a chunk into the `entities` directory next to
+/// `lvl_path`, replacing its region file.
+#[cfg(test)]
+pub(crate) fn write_entities(
+    lvl_path: &str,
+    (chunk_x, chunk_z): (i32, i32),
+    entities: Vec<Value>,
+) -> Result<()> {
+    let dir = Path::new(lvl_path).parent().unwrap().join("entities");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "r.{}.{}.mca",
+        chunk_x.div_euclid(32),
+        chunk_z.div_euclid(32)
+    ));
+    let chunk = fastnbt::nbt!({
+        "DataVersion": DATA_VERSION,
+        "Position": [I; chunk_x, chunk_z],
+        "Entities": Value::List(entities),
+    });
+
+    Region::new(
+        File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?,
+    )?
+    .write_chunk(
+        chunk_x.rem_euclid(32) as usize,
+        chunk_z.rem_euclid(32) as usize,
+        &fastnbt::to_bytes(&chunk)?,
+    )?;
+
+    Ok(())
+}
+
+/// Creates an empty directory in the system temp dir for a single test.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("mca_cuboids_{name}"));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir.to_str().unwrap().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::read_level_typed;
+    use crate::ExportParams;
+
+    fn read_back(dir: &str) -> Vec<(BlockSequence, String)> {
+        read_level_typed(
+            dir,
+            ExportParams {
+                start: BlockCoordinates::new(-100, -64, -100),
+                end: BlockCoordinates::new(200, 200, 100),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .merge()
+    }
+
+    #[test]
+    fn parse_block_state_with_properties() {
+        let state = parse_block_state("minecraft:oak_stairs[facing=east,half=top]").unwrap();
+
+        assert_eq!(state.name, "minecraft:oak_stairs");
+        assert_eq!(
+            state.properties,
+            BTreeMap::from([
+                ("facing".to_owned(), "east".to_owned()),
+                ("half".to_owned(), "top".to_owned()),
+            ])
+        );
+        assert!(parse_block_state("minecraft:stone[").is_err());
+        assert!(parse_block_state("minecraft:stone[waterlogged]").is_err());
+    }
+    #[test]
+    fn write_cuboids_round_trip() {
+        let dir = test_dir("write_cuboids_round_trip");
+        let cuboids = vec![
+            (
+                BlockSequence::new(
+                    BlockCoordinates::new(-20, -64, -3),
+                    BlockCoordinates::new(40, -60, 5),
+                ),
+                "minecraft:dirt",
+            ),
+            (
+                BlockSequence::new(
+                    BlockCoordinates::new(0, 100, 0),
+                    BlockCoordinates::new(0, 120, 0),
+                ),
+                "minecraft:oak_log[axis=y]",
+            ),
+        ];
+        write_cuboids(&dir, &cuboids, WriteMode::Replace).unwrap();
+
+        assert_eq!(
+            read_back(&dir),
+            vec![
+                (cuboids[0].0.clone(), "minecraft:dirt".to_owned()),
+                (cuboids[1].0.clone(), "minecraft:oak_log".to_owned()),
+            ]
+        );
+    }
+    #[test]
+    fn overlay_existing_world() {
+        let dir = test_dir("overlay_existing_world");
+        let b = BlockCoordinates::new;
+        let floor: Vec<(BlockCoordinates, &str)> =
+            (0..10).map(|x| (b(x, 0, 0), "minecraft:stone")).collect();
+        write_world(&dir, &floor).unwrap();
+
+        overlay_world(
+            &dir,
+            &[
+                (b(9, 0, 0), "minecraft:air"),
+                (b(0, 40, 0), "minecraft:dirt"),
+                (b(100, 0, 0), "minecraft:dirt"),
+            ],
+        )
+        .unwrap();
+        write_cuboids(
+            &dir,
+            &[(
+                BlockSequence::new(b(3, 0, 0), b(5, 0, 0)),
+                "minecraft:glass",
+            )],
+            WriteMode::Overlay,
+        )
+        .unwrap();
+
+        let cuboid = |start, end| BlockSequence::new(b(start, 0, 0), b(end, 0, 0));
+        assert_eq!(
+            read_back(&dir),
+            vec![
+                (cuboid(100, 100), "minecraft:dirt".to_owned()),
+                (
+                    BlockSequence::new(b(0, 40, 0), b(0, 40, 0)),
+                    "minecraft:dirt".to_owned()
+                ),
+                (cuboid(3, 5), "minecraft:glass".to_owned()),
+                (cuboid(0, 2), "minecraft:stone".to_owned()),
+                (cuboid(6, 8), "minecraft:stone".to_owned()),
+            ]
+        );
+
+        let chunk = RegionReader::open(&Path::new(&dir).join("r.0.0.mca"))
+            .unwrap()
+            .read_chunk(0, 0)
+            .unwrap()
+            .unwrap();
+        let Value::Compound(chunk) = fastnbt::from_bytes(&chunk).unwrap() else {
+            panic!("chunk is not a compound");
+        };
+        assert_eq!(chunk.get("Status"), Some(&Value::String("full".to_owned())));
+        assert_eq!(chunk.get("isLightOn"), Some(&Value::Byte(0)));
+    }
+    #[test]
+    fn write_cuboids_later_ones_win() {
+        let dir = test_dir("write_cuboids_later_ones_win");
+        let b = BlockCoordinates::new;
+        let cuboids = [
+            (
+                BlockSequence::new(b(14, 0, 0), b(18, 0, 0)),
+                "minecraft:stone",
+            ),
+            (
+                BlockSequence::new(b(16, 0, 0), b(16, 0, 0)),
+                "minecraft:air",
+            ),
+        ];
+
+        for mode in [WriteMode::Replace, WriteMode::Overlay] {
+            write_cuboids(&dir, &cuboids, mode).unwrap();
+
+            assert_eq!(
+                read_back(&dir),
+                vec![
+                    (
+                        BlockSequence::new(b(14, 0, 0), b(15, 0, 0)),
+                        "minecraft:stone".to_owned()
+                    ),
+                    (
+                        BlockSequence::new(b(17, 0, 0), b(18, 0, 0)),
+                        "minecraft:stone".to_owned()
+                    ),
+                ]
+            );
+        }
+    }
+    #[test]
+    fn overlay_world_updates_cache() {
+        let dir = test_dir("overlay_world_cache");
+        let b = BlockCoordinates::new;
+        let params = ExportParams {
+            start: b(0, 0, 0),
+            end: b(10, 10, 10),
+            cache_dir: Some(test_dir("overlay_world_cache_cache")),
+            ..Default::default()
+        };
+
+        write_world(&dir, &[(b(0, 0, 0), "minecraft:stone")]).unwrap();
+        crate::export_cuboids(&dir, params.clone()).unwrap();
+        overlay_world(&dir, &[(b(5, 5, 5), "minecraft:stone")]).unwrap();
+
+        assert_eq!(
+            crate::export_cuboids(&dir, params).unwrap(),
+            vec![
+                BlockSequence::new(b(0, 0, 0), b(0, 0, 0)),
+                BlockSequence::new(b(5, 5, 5), b(5, 5, 5)),
+            ]
+        );
+        let region = RegionReader::open(&Path::new(&dir).join("r.0.0.mca")).unwrap();
+        assert_ne!(region.timestamp(0, 0), 0);
+    }
+    #[test]
+    fn unpack_packed_states() {
+        let states: Vec<usize> = (0..SECTION_BLOCKS).map(|i| i % 17).collect();
+
+        assert_eq!(
+            unpack_states(&pack_states(&states, palette_bits(17)), 17).unwrap(),
+            states
+        );
+        let states: Vec<usize> = (0..SECTION_BLOCKS).map(|i| i % 20).collect();
+        assert!(unpack_states(&pack_states(&states, 5), 17).is_err());
+        assert!(unpack_states(&LongArray::new(vec![0; 10]), 2).is_err());
+    }
+    #[test]
+    fn pack_states_bits() {
+        assert_eq!(palette_bits(1), 4);
+        assert_eq!(palette_bits(16), 4);
+        assert_eq!(palette_bits(17), 5);
+
+        // 5 bits per block fit 12 blocks into a long
+        let states: Vec<usize> = (0..13).collect();
+        let packed = pack_states(&states, 5);
+        assert_eq!(packed.len(), 2);
+        assert_eq!(packed[1], 12);
+        assert_eq!((packed[0] >> 55) & 0b11111, 11);
+    }
+}